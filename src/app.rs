@@ -8,6 +8,7 @@ use crate::{
     pages::{
         apply_page::{ApplyPage, ApplyTui, ImageData},
         image_input::{ImageInputPage, ImageInputTui},
+        validation_page::ValidationPage,
         warning_page::WarningPage,
     },
     traits::get_input::DefaultInputComponent,
@@ -30,6 +31,7 @@ pub struct App {
     current_page: Pages,
     image_page: ImageInputPage,
     apply_page: ApplyPage,
+    validation_page: ValidationPage,
     warning_page: WarningPage,
     cfg: Config,
 }
@@ -44,22 +46,27 @@ pub enum Tui {
 pub enum Pages {
     Image,
     Apply(ImageData),
+    Validation,
     WarningPage,
 }
 
 impl App {
     pub fn new(cfg: Config) -> Self {
         let (tx, rx) = mpsc::channel::<Tui>(10);
+        let validation_page = ValidationPage::new(tx.clone(), cfg.clone());
         Self {
             exit: false,
             current_page: if cfg.is_first_time() {
                 Pages::WarningPage
+            } else if !validation_page.problems.is_empty() {
+                Pages::Validation
             } else {
                 Pages::Image
             },
             image_page: ImageInputPage::new(tx.clone()),
             apply_page: ApplyPage::new(tx.clone(), cfg.clone()),
-            warning_page: WarningPage::new(),
+            validation_page,
+            warning_page: WarningPage::new(cfg.get_roles()),
             cfg,
             tx,
             rx,
@@ -93,6 +100,9 @@ impl App {
                     Pages::Image => {
                         self.image_page.handle_event(event.into());
                     }
+                    Pages::Validation => {
+                        self.validation_page.handle_event(event);
+                    }
                     Pages::WarningPage => {}
                 }
             }
@@ -105,6 +115,7 @@ impl App {
                         self.apply_page.set_data(data.clone());
                     }
                     Pages::Image => {}
+                    Pages::Validation => {}
                     Pages::WarningPage => {}
                 }
                 self.current_page = page;
@@ -139,6 +150,9 @@ impl App {
             Pages::Image => {
                 self.image_page.draw(frame);
             }
+            Pages::Validation => {
+                self.validation_page.draw(frame);
+            }
             Pages::WarningPage => {
                 self.warning_page.draw(frame);
             }
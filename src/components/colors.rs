@@ -7,6 +7,8 @@ use ratatui_image::protocol::Protocol;
 use crate::traits::helpers::Separator;
 pub struct ColorComponent {
     state: Option<State>,
+    /// Share of the image for every color
+    shares: Vec<f64>,
     pub hex_colors: bool,
 }
 
@@ -19,6 +21,7 @@ impl ColorComponent {
     pub fn new() -> Self {
         Self {
             state: None,
+            shares: Vec::new(),
             hex_colors: false,
         }
     }
@@ -38,9 +41,20 @@ impl ColorComponent {
         }
     }
 
+    pub fn get_shares(&self) -> Vec<f64> {
+        self.shares.clone()
+    }
+
     pub fn set_path<T: ToString>(&mut self, path: T) {
         let path = path.to_string();
         let colors = image_palette::load(&path);
+        self.shares = match &colors {
+            Ok(colors) => {
+                let total = colors.0.iter().map(|x| x.count() as u64).sum::<u64>().max(1);
+                colors.0.iter().map(|x| x.count() as f64 / total as f64).collect()
+            }
+            Err(_) => Vec::new(),
+        };
         self.state = if colors.is_ok() {
             Some(State::COLORS(
                 colors
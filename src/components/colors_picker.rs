@@ -10,7 +10,10 @@ use ratatui::{
 };
 use ratatui_image::protocol::Protocol;
 
-use crate::traits::{get_input::InputComponent, helpers::Separator};
+use crate::{
+    helpers::color::RgbColor,
+    traits::{get_input::InputComponent, helpers::Separator},
+};
 pub struct ColorPicker {
     colors: Vec<String>,
     selected: SelectedColor,
@@ -44,7 +47,7 @@ impl ColorPicker {
     }
 
     pub fn with_pos(mut self, pos: usize) -> Self {
-        self.selected.pos = cmp::min(pos, self.colors.len());
+        self.selected.pos = cmp::min(pos, self.colors.len().saturating_sub(1));
         self
     }
 
@@ -75,21 +78,12 @@ impl ColorPicker {
     fn change_color_light(&mut self, light: i8) {
         let custom_color = self.get_custom_color();
         custom_color.light = light;
-        match Color::from_str(&custom_color.original).unwrap() {
-            Color::Rgb(r, g, b) => {
-                let modifier = (custom_color.light + 10) as f64 / 10.0;
-                let r = (r as f64 * modifier) as u8;
-                let g = (g as f64 * modifier) as u8;
-                let b = (b as f64 * modifier) as u8;
-
-                let color = Color::Rgb(r, g, b).to_string();
-                custom_color.modified = color;
-            }
-            _ => {
-                // ummmmm lets suppose its rgb ok
-                panic!("Color expected to be rgb");
-            }
-        }
+        let modifier = (custom_color.light + 10) as f64 / 10.0;
+        custom_color.modified = RgbColor::from_str(&custom_color.original)
+            // ummmmm lets suppose its rgb ok
+            .expect("Color expected to be rgb")
+            .scale(modifier)
+            .to_string();
     }
 
 
@@ -0,0 +1,198 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::config::{resolve_target, temp_path};
+
+const MANIFEST: &str = "manifest.json";
+
+/// Copies of destination files made right before one apply
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Backup {
+    /// Name of the backup directory, also used as id in `restore`
+    #[serde(skip)]
+    pub name: String,
+    #[serde(skip)]
+    pub dir: PathBuf,
+    pub created: String,
+    pub files: Vec<BackupFile>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BackupFile {
+    pub original: PathBuf,
+    /// Name of the copy inside backup directory, `None` if the file didn't exist yet
+    pub stored: Option<String>,
+}
+
+impl Backup {
+    /// Creates a new timestamped directory inside `root`
+    pub fn new(root: &Path) -> Result<Backup, String> {
+        let now = chrono::Local::now();
+        let base = now.format("%Y-%m-%d_%H-%M-%S").to_string();
+        let mut name = base.clone();
+        let mut count = 1;
+        while root.join(&name).exists() {
+            name = format!("{base}_{count}");
+            count += 1;
+        }
+        let dir = root.join(&name);
+        fs::create_dir_all(&dir)
+            .map_err(|err| format!("Failed to create backup {}: {err}", dir.display()))?;
+        Ok(Backup {
+            name,
+            dir,
+            created: now.format("%Y-%m-%d %H:%M:%S").to_string(),
+            files: vec![],
+        })
+    }
+
+    /// Keeps a copy of `path` before it's overwritten
+    pub fn save(&mut self, path: &Path) -> Result<(), String> {
+        if self.files.iter().any(|x| x.original == path) {
+            return Ok(());
+        }
+        let stored = if path.exists() {
+            let stored = format!(
+                "{}_{}",
+                self.files.len(),
+                path.file_name().and_then(|x| x.to_str()).unwrap_or("file")
+            );
+            fs::copy(path, self.dir.join(&stored))
+                .map_err(|err| format!("Failed to back up {}: {err}", path.display()))?;
+            Some(stored)
+        } else {
+            None
+        };
+        self.files.push(BackupFile {
+            original: path.to_path_buf(),
+            stored,
+        });
+        Ok(())
+    }
+
+    /// Writes the list of saved files, empty backups are removed
+    pub fn finish(&self) -> Result<(), String> {
+        if self.files.is_empty() {
+            return fs::remove_dir_all(&self.dir).map_err(|err| err.to_string());
+        }
+        let manifest = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        fs::write(self.dir.join(MANIFEST), manifest)
+            .map_err(|err| format!("Failed to write backup {}: {err}", self.dir.display()))
+    }
+
+    /// Puts every file back as it was, files that didn't exist are removed
+    pub fn restore(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .filter_map(|file| self.restore_file(file).err())
+            .collect()
+    }
+
+    /// Copy is written next to the original and renamed over it, like files written by apply
+    pub fn restore_file(&self, file: &BackupFile) -> Result<(), String> {
+        let res = match &file.stored {
+            Some(stored) => {
+                let target = resolve_target(&file.original);
+                let temp = temp_path(&target);
+                fs::copy(self.dir.join(stored), &temp)
+                    .and_then(|_| fs::rename(&temp, &target))
+                    .inspect_err(|_| {
+                        let _ = fs::remove_file(&temp);
+                    })
+            }
+            None if file.original.exists() => fs::remove_file(&file.original),
+            None => Ok(()),
+        };
+        res.map_err(|err| format!("Failed to restore {}: {err}", file.original.display()))
+    }
+
+    /// All backups in `root`, newest first
+    pub fn list(root: &Path) -> Vec<Backup> {
+        let Ok(entries) = fs::read_dir(root) else {
+            return vec![];
+        };
+        let mut backups = entries
+            .filter_map(|entry| {
+                let dir = entry.ok()?.path();
+                let manifest = fs::read_to_string(dir.join(MANIFEST)).ok()?;
+                let backup = serde_json::from_str::<Backup>(&manifest).ok()?;
+                Some(Backup {
+                    name: dir.file_name()?.to_str()?.to_string(),
+                    dir,
+                    ..backup
+                })
+            })
+            .collect::<Vec<Backup>>();
+        backups.sort_by(|a, b| b.name.cmp(&a.name));
+        backups
+    }
+
+    /// Finds backup by its name or by position in the list, 1 is the newest
+    pub fn find(root: &Path, id: &str) -> Result<Backup, String> {
+        let backups = Backup::list(root);
+        let found = match id.parse::<usize>() {
+            Ok(pos) => pos.checked_sub(1).and_then(|x| backups.get(x)),
+            Err(_) => backups.iter().find(|x| x.name == id),
+        };
+        found.cloned().ok_or(format!("Backup '{id}' not found"))
+    }
+
+    /// Removes the oldest backups so only `keep` are left
+    pub fn prune(root: &Path, keep: usize) -> Result<(), String> {
+        for backup in Backup::list(root).iter().skip(keep) {
+            fs::remove_dir_all(&backup.dir).map_err(|err| {
+                format!("Failed to remove backup {}: {err}", backup.dir.display())
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} ({} files: {})",
+            self.name,
+            self.files.len(),
+            self.files
+                .iter()
+                .map(|x| x.original.display().to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_replaces_file_behind_symlink() {
+        let root = std::env::temp_dir().join(format!("color-replacer-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("real")).unwrap();
+        let (real, link, new) = (
+            root.join("real/x.conf"),
+            root.join("x.conf"),
+            root.join("new.conf"),
+        );
+        fs::write(&real, "old").unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        let mut backup = Backup::new(&root.join("backups")).unwrap();
+        backup.save(&link).unwrap();
+        backup.save(&new).unwrap();
+        fs::write(&real, "new").unwrap();
+        fs::write(&new, "new").unwrap();
+        assert!(backup.restore().is_empty());
+
+        assert!(link.is_symlink());
+        assert_eq!(fs::read_to_string(&real).unwrap(), "old");
+        assert!(!new.exists());
+        assert_eq!(fs::read_dir(root.join("real")).unwrap().count(), 1);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,375 @@
+use std::{fmt::Display, str::FromStr};
+
+use ratatui::style::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RgbColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: f64, // [0 ; 1]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorFormat {
+    Hex,
+    HexNoPrefix,
+    Hex0x,
+    Rgb,
+    Rgba,
+    Hsl,
+    Decimal,
+    Float,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Harmony {
+    Complement,
+    Analogous1,
+    Analogous2,
+    Triad1,
+    Triad2,
+    Split1,
+    Split2,
+}
+
+impl RgbColor {
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b, a: 1.0 }
+    }
+
+    pub fn from_hsl(h: f64, s: f64, l: f64) -> Self {
+        let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        let h = h.rem_euclid(360.0) / 60.0;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u8 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = l - c / 2.0;
+        let channel = |v: f64| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+        Self::new(channel(r), channel(g), channel(b))
+    }
+
+    /// Returns (hue in degrees, saturation, lightness), the last two in [0 ; 1]
+    pub fn to_hsl(&self) -> (f64, f64, f64) {
+        let r = self.r as f64 / 255.0;
+        let g = self.g as f64 / 255.0;
+        let b = self.b as f64 / 255.0;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let l = (max + min) / 2.0;
+        let d = max - min;
+        if d == 0.0 {
+            return (0.0, 0.0, l);
+        }
+        let s = d / (1.0 - (2.0 * l - 1.0).abs());
+        let h = if max == r {
+            60.0 * ((g - b) / d).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / d + 2.0)
+        } else {
+            60.0 * ((r - g) / d + 4.0)
+        };
+        (h, s, l)
+    }
+
+    /// Multiplies every channel by `modifier`, same as lightening in the color picker
+    pub fn scale(&self, modifier: f64) -> Self {
+        Self {
+            r: (self.r as f64 * modifier) as u8,
+            g: (self.g as f64 * modifier) as u8,
+            b: (self.b as f64 * modifier) as u8,
+            a: self.a,
+        }
+    }
+
+    pub fn lighten(&self, amount: f64) -> Self {
+        self.scale(1.0 + amount)
+    }
+
+    pub fn darken(&self, amount: f64) -> Self {
+        self.scale(1.0 - amount)
+    }
+
+    pub fn saturate(&self, amount: f64) -> Self {
+        let (h, s, l) = self.to_hsl();
+        Self {
+            a: self.a,
+            ..Self::from_hsl(h, (s + amount).clamp(0.0, 1.0), l)
+        }
+    }
+
+    pub fn rotate_hue(&self, degrees: f64) -> Self {
+        let (h, s, l) = self.to_hsl();
+        Self {
+            a: self.a,
+            ..Self::from_hsl(h + degrees, s, l)
+        }
+    }
+
+    /// `weight` is the share of `other` in the result
+    pub fn mix(&self, other: &RgbColor, weight: f64) -> Self {
+        let weight = weight.clamp(0.0, 1.0);
+        let channel = |a: u8, b: u8| (a as f64 * (1.0 - weight) + b as f64 * weight).round() as u8;
+        Self {
+            r: channel(self.r, other.r),
+            g: channel(self.g, other.g),
+            b: channel(self.b, other.b),
+            a: self.a * (1.0 - weight) + other.a * weight,
+        }
+    }
+
+    pub fn invert(&self) -> Self {
+        Self {
+            r: 255 - self.r,
+            g: 255 - self.g,
+            b: 255 - self.b,
+            a: self.a,
+        }
+    }
+
+    pub fn with_alpha(&self, alpha: f64) -> Self {
+        Self {
+            a: alpha.clamp(0.0, 1.0),
+            ..*self
+        }
+    }
+
+    /// Relative luminance as defined by WCAG
+    pub fn luminance(&self) -> f64 {
+        let channel = |v: u8| {
+            let v = v as f64 / 255.0;
+            if v <= 0.03928 {
+                v / 12.92
+            } else {
+                ((v + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        0.2126 * channel(self.r) + 0.7152 * channel(self.g) + 0.0722 * channel(self.b)
+    }
+
+    pub fn contrast(&self, other: &RgbColor) -> f64 {
+        let (a, b) = (self.luminance(), other.luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    /// Dark colors are the ones white text is more readable on
+    pub fn is_dark(&self) -> bool {
+        self.contrast(&RgbColor::new(255, 255, 255)) > self.contrast(&RgbColor::new(0, 0, 0))
+    }
+
+    /// Black or white, whichever is more readable on top of this color
+    pub fn readable(&self) -> RgbColor {
+        let black = RgbColor::new(0, 0, 0);
+        let white = RgbColor::new(255, 255, 255);
+        if self.contrast(&white) >= self.contrast(&black) {
+            white
+        } else {
+            black
+        }
+    }
+
+    /// Color with the hue of this one and at least `ratio` contrast on top of it,
+    /// lightness is shifted towards black or white until it becomes readable
+    pub fn tinted(&self, ratio: f64) -> RgbColor {
+        let best = self.readable();
+        if self.contrast(&best) < ratio {
+            return best;
+        }
+        let (h, s, l) = self.to_hsl();
+        let target = if best == RgbColor::new(255, 255, 255) { 1.0 } else { 0.0 };
+        (1..=100)
+            .map(|step| Self::from_hsl(h, s, l + (target - l) * step as f64 / 100.0))
+            .find(|candidate| self.contrast(candidate) >= ratio)
+            .unwrap_or(best)
+    }
+
+    fn hex_alpha(&self) -> String {
+        if self.a < 1.0 {
+            format!("{:02X}", (self.a * 255.0).round() as u8)
+        } else {
+            String::new()
+        }
+    }
+
+    pub fn format(&self, format: ColorFormat) -> String {
+        let (r, g, b) = (self.r, self.g, self.b);
+        match format {
+            ColorFormat::Hex => format!("#{r:02X}{g:02X}{b:02X}{}", self.hex_alpha()),
+            ColorFormat::HexNoPrefix => format!("{r:02X}{g:02X}{b:02X}{}", self.hex_alpha()),
+            ColorFormat::Hex0x => format!("0x{r:02X}{g:02X}{b:02X}{}", self.hex_alpha()),
+            ColorFormat::Rgb => format!("rgb({r}, {g}, {b})"),
+            ColorFormat::Rgba => format!("rgba({r}, {g}, {b}, {})", round(self.a, 2)),
+            ColorFormat::Hsl => {
+                let (h, s, l) = self.to_hsl();
+                format!(
+                    "hsl({}, {}%, {}%)",
+                    h.round(),
+                    (s * 100.0).round(),
+                    (l * 100.0).round()
+                )
+            }
+            ColorFormat::Decimal => format!("{r}, {g}, {b}"),
+            ColorFormat::Float => format!(
+                "{:.3}, {:.3}, {:.3}",
+                r as f64 / 255.0,
+                g as f64 / 255.0,
+                b as f64 / 255.0
+            ),
+        }
+    }
+}
+
+fn round(value: f64, digits: i32) -> f64 {
+    let factor = 10f64.powi(digits);
+    (value * factor).round() / factor
+}
+
+impl FromStr for RgbColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Color::from_str(s) {
+            Ok(Color::Rgb(r, g, b)) => Ok(RgbColor::new(r, g, b)),
+            _ => Err(format!("'{s}' is not a rgb color")),
+        }
+    }
+}
+
+impl From<RgbColor> for Color {
+    fn from(value: RgbColor) -> Self {
+        Color::Rgb(value.r, value.g, value.b)
+    }
+}
+
+impl Display for RgbColor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.format(ColorFormat::Hex))
+    }
+}
+
+impl ColorFormat {
+    pub fn names() -> Vec<&'static str> {
+        vec![
+            "hex",
+            "hex-noprefix",
+            "hex-0x",
+            "rgb",
+            "rgba",
+            "hsl",
+            "decimal",
+            "float",
+        ]
+    }
+}
+
+impl FromStr for ColorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hex" => Ok(ColorFormat::Hex),
+            "hex-noprefix" => Ok(ColorFormat::HexNoPrefix),
+            "hex-0x" => Ok(ColorFormat::Hex0x),
+            "rgb" => Ok(ColorFormat::Rgb),
+            "rgba" => Ok(ColorFormat::Rgba),
+            "hsl" => Ok(ColorFormat::Hsl),
+            "decimal" => Ok(ColorFormat::Decimal),
+            "float" => Ok(ColorFormat::Float),
+            _ => Err(format!(
+                "Unknown color format '{s}', expected one of: {}",
+                ColorFormat::names().join(", ")
+            )),
+        }
+    }
+}
+
+impl Harmony {
+    pub fn all() -> Vec<Harmony> {
+        vec![
+            Harmony::Complement,
+            Harmony::Analogous1,
+            Harmony::Analogous2,
+            Harmony::Triad1,
+            Harmony::Triad2,
+            Harmony::Split1,
+            Harmony::Split2,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Harmony::Complement => "complement",
+            Harmony::Analogous1 => "analogous1",
+            Harmony::Analogous2 => "analogous2",
+            Harmony::Triad1 => "triad1",
+            Harmony::Triad2 => "triad2",
+            Harmony::Split1 => "split1",
+            Harmony::Split2 => "split2",
+        }
+    }
+
+    /// Hue offset from the base color in degrees
+    pub fn degrees(&self) -> f64 {
+        match self {
+            Harmony::Complement => 180.0,
+            Harmony::Analogous1 => -30.0,
+            Harmony::Analogous2 => 30.0,
+            Harmony::Triad1 => 120.0,
+            Harmony::Triad2 => 240.0,
+            Harmony::Split1 => 150.0,
+            Harmony::Split2 => 210.0,
+        }
+    }
+
+    pub fn apply(&self, color: &RgbColor) -> RgbColor {
+        color.rotate_hue(self.degrees())
+    }
+}
+
+impl FromStr for Harmony {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Harmony::all()
+            .into_iter()
+            .find(|x| x.name() == s)
+            .ok_or(format!(
+                "Unknown derived color '{s}', expected one of: {}",
+                Harmony::all()
+                    .iter()
+                    .map(|x| x.name())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readable_prefers_black_or_white() {
+        let light = RgbColor::from_str("#FFFFEA").unwrap();
+        assert!(light.readable() == RgbColor::new(0, 0, 0));
+        let blue = RgbColor::from_str("#0000FF").unwrap();
+        assert!(blue.readable() == RgbColor::new(255, 255, 255));
+    }
+
+    #[test]
+    fn tinted_meets_ratio() {
+        let light = RgbColor::from_str("#FFFFEA").unwrap();
+        let tinted = light.tinted(4.5);
+        assert!(light.contrast(&tinted) >= 4.5);
+        assert!(tinted != RgbColor::new(0, 0, 0));
+        // unreachable ratio falls back to the best of black and white
+        let gray = RgbColor::new(120, 120, 120);
+        assert!(gray.tinted(10.0) == gray.readable());
+    }
+}
@@ -1,22 +1,140 @@
 use std::{
+    collections::BTreeMap,
     fs::{self, File},
-    io::{Read, Write},
-    path::PathBuf,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
 };
 
+use clap::ValueEnum;
+use regex::{Regex, RegexBuilder};
+use similar::TextDiff;
 use serde::{Deserialize, Serialize};
 
+use super::{
+    backup::Backup,
+    color::{Harmony, RgbColor},
+    hooks::{Hook, HookResult},
+    structured::{self, FileFormat},
+    template::{self, Delimiters, Placeholder, TemplateOptions},
+    validation::{self, ValidationError},
+};
+
 const APP_KEY: &'static str = "colors_replacer";
+const MARKER_START: &str = "# color-replacer:start";
+const MARKER_END: &str = "# color-replacer:end";
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
     warning: FirstTimeStruct,
+    #[serde(default = "Role::defaults")]
+    roles: Vec<Role>,
+    /// WCAG level that `.on` colors should meet
+    #[serde(default)]
+    contrast: ContrastLevel,
+    #[serde(default)]
+    mode: Mode,
+    /// Role that decides whether palette is dark in auto mode, first role by default
+    #[serde(default)]
+    background: Option<String>,
+    /// Unknown placeholders fail the file instead of being left as is
+    #[serde(default)]
+    strict: bool,
+    /// Files are moved into place only when every one of them was rendered
+    #[serde(default)]
+    transactional: bool,
+    /// How many previous applies are kept in backups, 0 disables them
+    #[serde(default = "Config::default_backups")]
+    backups: usize,
+    /// Run before anything is written, get palette as json on stdin. Failure aborts the apply
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pre_apply: Vec<Hook>,
+    /// Run once after all files are written without errors
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    post_apply: Vec<Hook>,
     files: Vec<ReplaceFile>,
+    /// Directory of the config file, relative paths start from it
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+/// What happened during one apply
+#[derive(Default)]
+pub struct ApplyReport {
+    /// Set when a pre-apply hook failed and nothing was written
+    pub aborted: bool,
+    /// Problems that aren't about a single file
+    pub errors: Vec<String>,
+    pub files: Vec<FileResult>,
+    pub hooks: Vec<HookResult>,
+}
+
+#[derive(Clone, Debug)]
+pub enum FileStatus {
+    Written,
+    /// File already had the same content, it wasn't touched
+    Unchanged,
+    Skipped(String),
+    Failed(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct FileResult {
+    pub to: PathBuf,
+    pub status: FileStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Role {
+    pub name: String,
+    /// Position of the color in the extracted palette that is selected by default
+    pub index: usize,
 }
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ReplaceFile {
-    pub from: PathBuf,
+    /// Template that is rendered into `to`, without it `to` is only changed by `rules`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<PathBuf>,
     pub to: PathBuf,
+    /// Removed from names of files found when `from` is a directory or glob, e.g. `.tpl`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strip_suffix: Option<String>,
+    /// Replaces `$[` and `]` in this file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delimiters: Option<Delimiters>,
+    /// Overrides `strict` from config for this file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+    /// Only the part of `to` between these lines is replaced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub markers: Option<Markers>,
+    /// Applied to the result after the template is rendered
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+    /// Values set by key path in json, toml, yaml or ini file, e.g. `a.b."c.d"` -> `$[primary]`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, String>,
+    /// Format of `to` for `keys`, guessed from its extension by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<FileFormat>,
+    /// Run after this file is written
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_apply: Vec<Hook>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Rule {
+    /// Regex where the first capture group, or the whole match without groups, is replaced
+    pub pattern: String,
+    /// Placeholder without delimiters, e.g. `primary` or `primary|darken(10%):rgb`
+    pub color: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Markers {
+    pub start: String,
+    pub end: String,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -25,119 +143,1372 @@ pub struct FirstTimeStruct {
     text: String,
 }
 #[derive(Debug)]
-
 pub struct ReplaceColors<T> {
-    pub primary: T,
-    pub secondary: T,
-    pub tertiary: T,
+    pub roles: Vec<(String, T)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ContrastLevel {
+    #[default]
+    AA,
+    AAA,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Auto,
+    Dark,
+    Light,
+}
+
+/// Everything that can be inserted into files
+#[derive(Debug)]
+pub struct Palette {
+    pub roles: ReplaceColors<String>,
+    pub colors: Vec<String>,
+    /// Share of the image covered by every color in `colors`, in [0 ; 1]
+    pub shares: Vec<f64>,
+    pub contrast: ContrastLevel,
+    pub dark: bool,
+}
+
+impl Role {
+    pub fn defaults() -> Vec<Role> {
+        ["primary", "secondary", "tertiary"]
+            .iter()
+            .enumerate()
+            .map(|(index, name)| Role {
+                name: name.to_string(),
+                index,
+            })
+            .collect()
+    }
 }
 
 impl<T> ReplaceColors<T> {
-    pub fn get_params() -> Vec<String> {
-        vec!["primary", "secondary", "tertiary"].iter().map(|x| x.to_string()).collect()
+    pub fn new(roles: Vec<(String, T)>) -> Self {
+        Self { roles }
+    }
+    pub fn get_params(&self) -> Vec<String> {
+        self.roles.iter().map(|(name, _)| name.clone()).collect()
     }
     pub fn get_pairs(&self) -> Vec<(String, &T)> {
-        vec![
-            ("primary".into()  ,  &self.primary),
-            ("secondary".into(), &self.secondary),
-            ("tertiary".into() , &self.tertiary)
-        ]
+        self.roles.iter().map(|(name, x)| (name.clone(), x)).collect()
+    }
+    pub fn get(&self, key: &str) -> Option<&T> {
+        self.roles.iter().find(|(name, _)| name == key).map(|(_, x)| x)
+    }
+    pub fn map<U, F: Fn(&T) -> U>(&self, f: F) -> ReplaceColors<U> {
+        ReplaceColors::new(self.roles.iter().map(|(name, x)| (name.clone(), f(x))).collect())
+    }
+}
+
+impl ContrastLevel {
+    /// Minimal contrast ratio for normal text
+    pub fn ratio(&self) -> f64 {
+        match self {
+            ContrastLevel::AA => 4.5,
+            ContrastLevel::AAA => 7.0,
+        }
+    }
+}
+
+impl Mode {
+    pub fn next(&self) -> Mode {
+        match self {
+            Mode::Auto => Mode::Dark,
+            Mode::Dark => Mode::Light,
+            Mode::Light => Mode::Auto,
+        }
+    }
+}
+
+impl Palette {
+    /// Returns `Ok(None)` if key is not a color at all, such keys are left untouched
+    pub fn lookup(&self, key: &str) -> Result<Option<RgbColor>, String> {
+        if let Some((base, derived)) = key.split_once('.') {
+            return match self.lookup(base)? {
+                Some(color) if derived == "on" => Ok(Some(color.readable())),
+                Some(color) if derived == "on_tint" => {
+                    Ok(Some(color.tinted(self.contrast.ratio())))
+                }
+                Some(color) => Ok(Some(Harmony::from_str(derived)?.apply(&color))),
+                None => Ok(None),
+            };
+        }
+        if let Some(color) = self.roles.get(key) {
+            return RgbColor::from_str(color).map(Some);
+        }
+        match key.strip_prefix("color").map(str::parse::<usize>) {
+            Some(Ok(pos)) => match self.colors.get(pos) {
+                Some(color) => RgbColor::from_str(color).map(Some),
+                None => Err(format!(
+                    "'{key}' doesn't exist, image has only {} colors",
+                    self.colors.len()
+                )),
+            },
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Default for Markers {
+    fn default() -> Self {
+        Self {
+            start: MARKER_START.to_string(),
+            end: MARKER_END.to_string(),
+        }
+    }
+}
+
+impl Markers {
+    /// Byte range of `target` between the start marker line and the end marker line
+    pub fn region(&self, target: &str) -> Result<(usize, usize), String> {
+        let start = target
+            .find(&self.start)
+            .ok_or(format!("'{}' marker not found", self.start))?;
+        let start = target[start..]
+            .find('\n')
+            .map_or(target.len(), |x| start + x + 1);
+        let end = target[start..]
+            .find(&self.end)
+            .ok_or(format!("'{}' marker not found after '{}'", self.end, self.start))?;
+        let end = target[..start + end].rfind('\n').map_or(start, |x| (x + 1).max(start));
+        Ok((start, end))
+    }
+
+    /// Puts `content` between the markers, everything else is kept as is
+    pub fn splice(&self, target: &str, content: &str) -> Result<String, String> {
+        let (start, end) = self.region(target)?;
+        // end marker has to stay on its own line
+        let newline = if content.is_empty() || content.ends_with('\n') { "" } else { "\n" };
+        Ok(format!("{}{content}{newline}{}", &target[..start], &target[end..]))
+    }
+}
+
+impl Rule {
+    fn regex(&self) -> Result<Regex, String> {
+        RegexBuilder::new(&self.pattern)
+            .multi_line(true)
+            .build()
+            .map_err(|err| format!("Invalid rule '{}': {err}", self.pattern))
+    }
+
+    /// Returns new content and how many times the rule matched
+    pub fn apply(&self, data: &str, palette: &Palette) -> Result<(String, usize), String> {
+        let regex = self.regex()?;
+        let options = TemplateOptions {
+            path: PathBuf::new(),
+            delimiters: Delimiters::default(),
+            strict: true,
+        };
+        let value = template::render(&Config::replace_key(self.color.clone()), palette, &options)
+            .map_err(|err| format!("Rule '{}': {}", self.pattern, err.message))?;
+        let mut out = String::with_capacity(data.len());
+        let mut last = 0;
+        let mut count = 0;
+        // with groups only the first one is replaced, matches where it is empty are skipped
+        let group = if regex.captures_len() > 1 { 1 } else { 0 };
+        for captures in regex.captures_iter(data) {
+            let Some(group) = captures.get(group) else {
+                continue;
+            };
+            out.push_str(&data[last..group.start()]);
+            out.push_str(&value);
+            last = group.end();
+            count += 1;
+        }
+        out.push_str(&data[last..]);
+        Ok((out, count))
+    }
+}
+
+impl Palette {
+    /// Roles, image colors and mode, as given to pre-apply hooks
+    pub fn to_json(&self) -> String {
+        let roles = self
+            .roles
+            .get_pairs()
+            .into_iter()
+            .map(|(name, color)| (name, serde_json::Value::from(color.as_str())))
+            .collect::<serde_json::Map<String, serde_json::Value>>();
+        serde_json::json!({
+            "roles": roles,
+            "colors": self.colors,
+            "shares": self.shares,
+            "mode": if self.dark { "dark" } else { "light" },
+        })
+        .to_string()
+    }
+}
+
+/// Expands `~`, `$VAR` and `${VAR}`, relative paths start from `base`
+pub fn expand_path(path: &Path, base: &Path) -> Result<PathBuf, String> {
+    let raw = path.to_string_lossy();
+    let expanded = shellexpand::full(&raw)
+        .map_err(|err| format!("{raw}: variable '{}' is not defined", err.var_name))?;
+    Ok(base.join(expanded.as_ref()))
+}
+
+fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+/// Splits a glob into the directory that is walked and the pattern below it,
+/// `None` when `path` isn't a glob. Parts that exist as they are, like `[2020]`, stay literal
+fn split_glob(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    let mut base = PathBuf::new();
+    let mut parts = path.components();
+    while let Some(part) = parts.next() {
+        if is_glob(Path::new(&part)) && !base.join(part).exists() {
+            return Some((base, std::iter::once(part).chain(parts).collect()));
+        }
+        base.push(part);
     }
+    None
+}
+
+/// Every file inside `dir` and its subdirectories
+fn walk_dir(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut entries = fs::read_dir(dir)
+        .map_err(|err| format!("{}: {err}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|x| x.path()))
+        .collect::<Vec<PathBuf>>();
+    entries.sort();
+    let mut files = vec![];
+    for path in entries {
+        // symlinked directories are skipped, they can point back to a parent
+        let linked = fs::symlink_metadata(&path).is_ok_and(|x| x.file_type().is_symlink());
+        if linked && path.is_dir() {
+            continue;
+        }
+        if path.is_dir() {
+            files.extend(walk_dir(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// File that is replaced when writing to `path`, symlinks are followed
+pub fn resolve_target(path: &Path) -> PathBuf {
+    if path.is_symlink() {
+        fs::canonicalize(path).unwrap_or(path.to_path_buf())
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Sibling of `target` that gets new content before it is renamed over `target`
+pub fn temp_path(target: &Path) -> PathBuf {
+    let name = target.file_name().and_then(|x| x.to_str()).unwrap_or("file");
+    target.with_file_name(format!(".{name}.color-replacer.tmp"))
 }
 
 impl ReplaceFile {
-    pub fn replace(&self, colors: &ReplaceColors<String>) {
-        let mut file_in = fs::File::open(self.from.clone())
-            .expect(&format!("Failed to open 'from' file {:?}", self.from));
-        let mut file_out = fs::File::create(self.to.clone())
-            .expect(&format!("Failed to open 'to' file {:?}", self.from));
-        let mut data = String::new();
-        file_in
-            .read_to_string(&mut data)
-            .expect(&format!("Failed to read 'from' file {:?}", self.from));
+    pub fn new(from: PathBuf, to: PathBuf) -> Self {
+        Self {
+            from: Some(from),
+            to,
+            strip_suffix: None,
+            delimiters: None,
+            strict: None,
+            markers: None,
+            rules: vec![],
+            keys: BTreeMap::new(),
+            format: None,
+            post_apply: vec![],
+        }
+    }
+
+    pub fn get_format(&self) -> Result<FileFormat, String> {
+        self.format
+            .or(FileFormat::from_path(&self.to))
+            .ok_or("Format of the file is unknown, set it with \"format\"".to_string())
+    }
+
+    /// Renders every value of `keys` and puts it into `data`
+    fn set_keys(&self, data: String, palette: &Palette, strict: bool) -> Result<String, String> {
+        if self.keys.is_empty() {
+            return Ok(data);
+        }
+        let format = self.get_format()?;
+        self.keys.iter().try_fold(data, |data, (key, value)| {
+            let value = template::render(value, palette, &self.options(strict))
+                .map_err(|err| format!("Key '{key}': {}", err.message))?;
+            format
+                .set(&data, &structured::split_key(key)?, &value)
+                .map_err(|err| format!("Key '{key}': {err}"))
+        })
+    }
+
+    /// Files found when `from` is a directory or glob, every one goes to the same
+    /// relative path inside `to`. Variables in paths are expanded, relative paths start from `base`
+    pub fn expand(&self, base: &Path) -> Result<Vec<ReplaceFile>, String> {
+        let file = ReplaceFile {
+            from: self.from.as_ref().map(|x| expand_path(x, base)).transpose()?,
+            to: expand_path(&self.to, base)?,
+            ..self.clone()
+        };
+        let Some(from) = &file.from else {
+            return Ok(vec![file]);
+        };
+        let (base, found) = if from.is_dir() {
+            (from.clone(), walk_dir(from)?)
+        } else if let Some((base, rest)) = split_glob(from) {
+            let pattern = Path::new(&glob::Pattern::escape(&base.to_string_lossy())).join(rest);
+            let pattern = glob::Pattern::new(&pattern.to_string_lossy())
+                .map_err(|err| format!("{}: {err}", from.display()))?;
+            let options = glob::MatchOptions {
+                require_literal_separator: true,
+                ..glob::MatchOptions::new()
+            };
+            // walking the base instead of globbing keeps symlinked directories out
+            let found = if base.is_dir() { walk_dir(&base)? } else { vec![] }
+                .into_iter()
+                .filter(|x| pattern.matches_path_with(x, options) && x.is_file())
+                .collect::<Vec<PathBuf>>();
+            (base, found)
+        } else {
+            return Ok(vec![file]);
+        };
+        Ok(found
+            .into_iter()
+            .map(|path| {
+                let mut to = file.to.join(path.strip_prefix(&base).unwrap_or(&path));
+                let name = to.file_name().and_then(|x| x.to_str()).map(String::from);
+                if let (Some(name), Some(suffix)) = (name, &file.strip_suffix) {
+                    if let Some(name) = name.strip_suffix(suffix.as_str()) {
+                        to.set_file_name(name);
+                    }
+                }
+                ReplaceFile {
+                    from: Some(path),
+                    to,
+                    ..file.clone()
+                }
+            })
+            .collect())
+    }
+
+    /// File that is read before replacing, `to` itself when there is no template
+    pub fn source(&self) -> &PathBuf {
+        self.from.as_ref().unwrap_or(&self.to)
+    }
+
+    pub fn options(&self, strict: bool) -> TemplateOptions {
+        TemplateOptions {
+            path: self.source().clone(),
+            delimiters: self.delimiters.clone().unwrap_or_default(),
+            strict: self.strict.unwrap_or(strict),
+        }
+    }
+
+    /// New content of `to`, nothing is written
+    pub fn render(&self, palette: &Palette, strict: bool) -> Result<String, String> {
+        self.render_checked(palette, strict).map(|(data, _)| data)
+    }
+
+    /// Same as `render`, also returns warnings about rules that matched nothing
+    pub fn render_checked(
+        &self,
+        palette: &Palette,
+        strict: bool,
+    ) -> Result<(String, Vec<String>), String> {
+        let data = fs::read_to_string(self.source())
+            .map_err(|err| format!("{}: {err}", self.source().display()))?;
+
+        let data = match &self.from {
+            Some(from) => template::render(&data, palette, &self.options(strict))
+                .map_err(|err| format!("{}:{err}", from.display()))?,
+            None => data,
+        };
+        let data = match &self.markers {
+            Some(markers) => fs::read_to_string(&self.to)
+                .map_err(|err| err.to_string())
+                .and_then(|target| markers.splice(&target, &data))
+                .map_err(|err| format!("{}: {err}", self.to.display()))?,
+            None => data,
+        };
+        let mut warnings = vec![];
+        // rules see the rendered template, not what is in `to` now
+        let data = self.rules.iter().try_fold(data, |data, rule| {
+            let (data, count) = rule
+                .apply(&data, palette)
+                .map_err(|err| format!("{}: {err}", self.to.display()))?;
+            if count == 0 {
+                warnings.push(format!(
+                    "{}: rule '{}' doesn't match anything",
+                    self.to.display(),
+                    rule.pattern
+                ));
+            }
+            Ok::<String, String>(data)
+        })?;
+        let data = self
+            .set_keys(data, palette, strict)
+            .map_err(|err| format!("{}: {err}", self.to.display()))?;
+        Ok((data, warnings))
+    }
 
-        for (key, color) in colors.get_pairs() {
-            data = data.replace(&Config::replace_key(key), color);
+    /// Unified diff between current `to` and what would be written, empty if nothing changes
+    pub fn diff(&self, palette: &Palette, strict: bool) -> Result<String, String> {
+        let new = self.render(palette, strict)?;
+        let old = fs::read_to_string(&self.to).unwrap_or_default();
+        let name = self.to.display().to_string();
+        Ok(TextDiff::from_lines(&old, &new)
+            .unified_diff()
+            .header(&name, &name)
+            .to_string())
+    }
+
+    /// File that is actually replaced, the target when `to` is a symlink
+    fn target(&self) -> PathBuf {
+        resolve_target(&self.to)
+    }
+
+    /// Renders new content of `to`, `None` if `to` already has it. Nothing is created,
+    /// directories and the temporary file are left to `commit`
+    pub fn stage(&self, palette: &Palette, strict: bool) -> Result<Option<String>, String> {
+        let data = self.render(palette, strict)?;
+        if fs::read_to_string(&self.to).is_ok_and(|x| x == data) {
+            return Ok(None);
+        }
+        Ok(Some(data))
+    }
+
+    /// Runs hooks of the file, commands from `ran` already ran during this apply
+    fn run_hooks(&self, env: &[(String, String)], ran: &mut Vec<String>) -> Vec<HookResult> {
+        let mut results = vec![];
+        for hook in &self.post_apply {
+            if !ran.contains(&hook.command) {
+                ran.push(hook.command.clone());
+                results.push(hook.run(env, None));
+            }
+        }
+        results
+    }
+
+    /// Writes staged content into a temporary file next to `to` and moves it into place,
+    /// readers see either the old or the new content
+    pub fn commit(&self, data: &str, backup: Option<&mut Backup>) -> Result<(), String> {
+        let target = self.target();
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|err| format!("{}: {err}", parent.display()))?;
+        }
+        let temp = temp_path(&target);
+        fs::write(&temp, data).map_err(|err| format!("{}: {err}", temp.display()))?;
+        // renamed file replaces the old one, so it keeps the old permissions
+        if let Ok(meta) = fs::metadata(&target) {
+            let _ = fs::set_permissions(&temp, meta.permissions());
+        }
+        let res = match backup {
+            Some(backup) => backup.save(&self.to),
+            None => Ok(()),
+        }
+        .and_then(|_| {
+            fs::rename(&temp, &target).map_err(|err| format!("{}: {err}", self.to.display()))
+        });
+        if res.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        res
+    }
+}
+
+impl ApplyReport {
+    pub fn failed(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|x| matches!(x.status, FileStatus::Failed(_)))
+            .count()
+    }
+
+    /// Number of files with each status, like `2 written, 1 failed`
+    pub fn summary(&self) -> String {
+        ["written", "unchanged", "skipped", "failed"]
+            .iter()
+            .filter_map(|name| {
+                let count = self
+                    .files
+                    .iter()
+                    .filter(|x| x.status.name() == *name)
+                    .count();
+                (count > 0).then(|| format!("{count} {name}"))
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    /// Puts back files written before `failed`, only files that couldn't be put back stay in `backup`
+    fn roll_back(&mut self, backup: &mut Backup, failed: &Path) {
+        let mut kept = vec![];
+        for result in &mut self.files {
+            if !matches!(result.status, FileStatus::Written) {
+                continue;
+            }
+            let Some(saved) = backup.files.iter().find(|x| x.original == result.to) else {
+                continue;
+            };
+            match backup.restore_file(saved) {
+                Ok(()) => {
+                    result.status =
+                        FileStatus::Skipped(format!("rolled back, {} failed", failed.display()))
+                }
+                Err(err) => {
+                    self.errors.push(err);
+                    kept.push(saved.clone());
+                }
+            }
+        }
+        backup.files = kept;
+    }
+}
+
+impl FileStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileStatus::Written => "written",
+            FileStatus::Unchanged => "unchanged",
+            FileStatus::Skipped(_) => "skipped",
+            FileStatus::Failed(_) => "failed",
+        }
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            FileStatus::Skipped(reason) | FileStatus::Failed(reason) => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+impl FileResult {
+    fn skip_all(files: &[ReplaceFile], reason: &str) -> Vec<FileResult> {
+        files
+            .iter()
+            .map(|file| FileResult {
+                to: file.to.clone(),
+                status: FileStatus::Skipped(reason.to_string()),
+            })
+            .collect()
+    }
+}
+
+/// Config that is used before the user has set anything
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            files: vec![],
+            roles: Role::defaults(),
+            contrast: ContrastLevel::default(),
+            mode: Mode::default(),
+            background: None,
+            strict: false,
+            transactional: false,
+            backups: Config::default_backups(),
+            pre_apply: vec![],
+            post_apply: vec![],
+            dir: PathBuf::new(),
+            warning: FirstTimeStruct {
+                first_time: true,
+                text: "Set first_time to false in order to continue!".to_string(),
+            },
         }
-        file_out
-            .write_all(data.as_bytes())
-            .expect(&format!("Failed to write into file {:?}", self.to));
     }
 }
 
 impl Config {
-    pub fn new() -> Config {
+    pub fn new() -> Result<Config, String> {
         let mut path = dirs::config_dir().expect("Couldn't get path for config directory");
         path.push(APP_KEY);
         if !path.exists() {
-            fs::create_dir(&path).unwrap();
+            fs::create_dir(&path)
+                .map_err(|err| format!("Couldn't create {}: {err}", path.display()))?;
         };
         let config_file = Config::get_config_path();
         if !config_file.exists() {
-            let mut file = fs::File::create(&config_file).unwrap();
-            file.write_all(
-                serde_json::to_string_pretty(&Config {
-                    files: vec![
-                        ReplaceFile {
-                            from: "/example/path/from".into(),
-                            to: "/example/path/to".into(),
-                        },
-                        ReplaceFile {
-                            from: "/example/path/from2".into(),
-                            to: "/example/path/to2".into(),
-                        },
-                    ],
-                    warning: FirstTimeStruct {
-                        first_time: true,
-                        text: "Set first_time to false in order to continue!".to_string(),
-                    },
-                })
-                .unwrap()
-                .as_bytes(),
-            )
-            .unwrap();
-            return Config {
-                files: vec![],
-                warning: FirstTimeStruct {
-                    first_time: true,
-                    text: "Set first_time to false in order to continue!".to_string(),
-                },
+            let mut file = fs::File::create(&config_file)
+                .map_err(|err| format!("Couldn't create {}: {err}", config_file.display()))?;
+            let example = Config {
+                files: vec![
+                    ReplaceFile::new("/example/path/from".into(), "/example/path/to".into()),
+                    ReplaceFile::new("/example/path/from2".into(), "/example/path/to2".into()),
+                ],
+                ..Config::default()
             };
+            file.write_all(serde_json::to_string_pretty(&example).unwrap().as_bytes())
+                .map_err(|err| format!("Couldn't write {}: {err}", config_file.display()))?;
+            return Ok(Config {
+                dir: path,
+                ..Config::default()
+            });
         };
         Config::from_path(config_file)
     }
 
-    pub fn from_path(path: PathBuf) -> Config {
-        let mut file = fs::File::open(&path).expect(&format!("Couldn't open file {:?}", path));
-        let mut buf = String::new();
-        file.read_to_string(&mut buf)
-            .expect(&format!("Couldn't read file {:?}", path));
-        serde_json::from_str(&buf)
-            .expect(&format!("Failed to serialize config file {:?}", path))
+    /// Format is picked by extension, json is read as json5 so comments and trailing commas are allowed
+    pub fn from_path(path: PathBuf) -> Result<Config, String> {
+        let buf = fs::read_to_string(&path)
+            .map_err(|err| format!("Couldn't read config {}: {err}", path.display()))?;
+        let cfg: Config = match FileFormat::from_path(&path) {
+            Some(FileFormat::Toml) => toml::from_str(&buf).map_err(|err| {
+                format!("Failed to parse toml config {}: {err}", path.display())
+            })?,
+            Some(FileFormat::Yaml) => serde_norway::from_str(&buf).map_err(|err| {
+                format!("Failed to parse yaml config {}: {err}", path.display())
+            })?,
+            Some(FileFormat::Ini) => {
+                return Err(format!("Ini configs are not supported: {}", path.display()))
+            }
+            Some(FileFormat::Json) | None => json5::from_str(&buf).map_err(|err| {
+                format!("Failed to parse json config {}: {err}", path.display())
+            })?,
+        };
+        for (pos, file) in cfg.files.iter().enumerate() {
+            if let Some(delimiters) = &file.delimiters {
+                delimiters.check().map_err(|err| {
+                    format!("Invalid config {}: files[{pos}]: {err}", path.display())
+                })?;
+            }
+        }
+        let path = fs::canonicalize(&path).unwrap_or(path);
+        Ok(Config {
+            dir: path.parent().map(PathBuf::from).unwrap_or_default(),
+            ..cfg
+        })
     }
 
     pub fn is_first_time(&self) -> bool {
         self.warning.first_time
     }
+    /// First existing config.toml, config.yaml, config.yml or config.json,
+    /// config.json if there is none yet
     pub fn get_config_path() -> PathBuf {
         let path = dirs::config_dir().expect("Couldn't get path for config directory");
         let mut path = path.clone();
         path.push(APP_KEY);
-        path.push("config.json");
+        ["config.toml", "config.yaml", "config.yml"]
+            .iter()
+            .map(|name| path.join(name))
+            .find(|x| x.exists())
+            .unwrap_or(path.join("config.json"))
+    }
+
+    pub fn get_backup_dir() -> PathBuf {
+        let mut path = dirs::config_dir().expect("Couldn't get path for config directory");
+        path.push(APP_KEY);
+        path.push("backups");
         path
     }
 
-    pub fn process(&self, colors: &ReplaceColors<String>) {
-        for file in &self.files {
-            file.replace(colors);
+    fn default_backups() -> usize {
+        10
+    }
+
+    fn start_backup(&self) -> Result<Option<Backup>, String> {
+        if self.backups == 0 {
+            return Ok(None);
+        }
+        Backup::new(&Config::get_backup_dir()).map(Some)
+    }
+
+    fn finish_backup(&self, backup: Option<Backup>) -> Option<String> {
+        backup?
+            .finish()
+            .and_then(|_| Backup::prune(&Config::get_backup_dir(), self.backups))
+            .err()
+    }
+
+    /// Roles passed to hooks, `primary` becomes `COLOR_PRIMARY`
+    pub fn hook_env(palette: &Palette) -> Vec<(String, String)> {
+        let mut env = palette
+            .roles
+            .get_pairs()
+            .into_iter()
+            .map(|(name, color)| {
+                let name = name
+                    .chars()
+                    .map(|x| if x.is_ascii_alphanumeric() { x.to_ascii_uppercase() } else { '_' })
+                    .collect::<String>();
+                (format!("COLOR_{name}"), color.clone())
+            })
+            .collect::<Vec<(String, String)>>();
+        let mode = if palette.dark { "dark" } else { "light" };
+        env.push(("COLOR_MODE".to_string(), mode.to_string()));
+        env
+    }
+
+    /// Files that couldn't be rendered are reported in errors, other files are still written
+    pub fn process(&self, palette: &Palette) -> ApplyReport {
+        let mut report = ApplyReport::default();
+        // entries that can't be expanded are reported by validation
+        let (files, _) = self.expand_files();
+        let problems = self.validate();
+        // a problem fails only its own entries, other files are still written
+        let blocked = files
+            .iter()
+            .map(|file| {
+                problems
+                    .iter()
+                    .find(|x| x.concerns(file.from.as_deref(), &file.to))
+                    .map(|x| x.to_string())
+            })
+            .collect::<Vec<Option<String>>>();
+        report.errors = problems
+            .iter()
+            .filter(|x| {
+                !files
+                    .iter()
+                    .any(|file| x.concerns(file.from.as_deref(), &file.to))
+            })
+            .map(|x| x.to_string())
+            .collect();
+        if self.transactional && !problems.is_empty() {
+            report.files = files
+                .iter()
+                .zip(&blocked)
+                .map(|(file, reason)| FileResult {
+                    to: file.to.clone(),
+                    status: match reason {
+                        Some(reason) => FileStatus::Failed(reason.clone()),
+                        None => FileStatus::Skipped("config has problems".to_string()),
+                    },
+                })
+                .collect();
+            return report;
+        }
+        let env = Config::hook_env(palette);
+        let json = palette.to_json();
+        for hook in &self.pre_apply {
+            let result = hook.run(&env, Some(&json));
+            if !result.success() {
+                report.aborted = true;
+                report.errors.push(format!("Apply aborted, pre-apply hook {}", result.describe()));
+                report.files = FileResult::skip_all(&files, "aborted by pre-apply hook");
+                report.hooks.push(result);
+                return report;
+            }
+            report.hooks.push(result);
+        }
+        let mut backup = match self.start_backup() {
+            Ok(backup) => backup,
+            Err(err) => {
+                report.errors.push(err);
+                report.files = FileResult::skip_all(&files, "backup failed");
+                return report;
+            }
+        };
+        // without backups transactional apply still needs old files to undo a failed rename
+        let undo_dir = std::env::temp_dir().join(format!("color-replacer-{}", std::process::id()));
+        let keep_backup = backup.is_some();
+        if backup.is_none() && self.transactional {
+            match Backup::new(&undo_dir) {
+                Ok(undo) => backup = Some(undo),
+                Err(err) => {
+                    report.errors.push(err);
+                    report.files = FileResult::skip_all(&files, "backup failed");
+                    return report;
+                }
+            }
+        }
+        // every file is rendered before any of them is moved into place
+        let staged = files
+            .iter()
+            .zip(blocked)
+            .map(|(file, reason)| match reason {
+                Some(reason) => Err(reason),
+                None => file.stage(palette, self.strict),
+            })
+            .collect::<Vec<Result<Option<String>, String>>>();
+        let mut cancel = self.transactional && staged.iter().any(|x| x.is_err());
+        // a command runs once per apply even when several written files list it,
+        // like files found in one directory or glob
+        let mut ran = vec![];
+        // hooks of transactional apply wait until every file is in place
+        let mut written = vec![];
+        for (file, stage) in files.iter().zip(staged) {
+            let status = match stage {
+                Ok(Some(_)) if cancel => FileStatus::Skipped("another file failed".to_string()),
+                Ok(Some(data)) => match file.commit(&data, backup.as_mut()) {
+                    Ok(()) if self.transactional => {
+                        written.push(file);
+                        FileStatus::Written
+                    }
+                    Ok(()) => {
+                        report.hooks.extend(file.run_hooks(&env, &mut ran));
+                        FileStatus::Written
+                    }
+                    Err(err) if self.transactional => {
+                        cancel = true;
+                        written.clear();
+                        if let Some(backup) = backup.as_mut() {
+                            report.roll_back(backup, &file.to);
+                        }
+                        FileStatus::Failed(err)
+                    }
+                    Err(err) => FileStatus::Failed(err),
+                },
+                Ok(None) => FileStatus::Unchanged,
+                Err(err) => FileStatus::Failed(err),
+            };
+            report.files.push(FileResult {
+                to: file.to.clone(),
+                status,
+            });
+        }
+        for file in written {
+            report.hooks.extend(file.run_hooks(&env, &mut ran));
+        }
+        if keep_backup {
+            report.errors.extend(self.finish_backup(backup));
+        } else if let Some(backup) = backup {
+            let _ = fs::remove_dir_all(&backup.dir);
+            // other applies of this process can still use the root
+            let _ = fs::remove_dir(&undo_dir);
+        }
+        if report.errors.is_empty() && report.failed() == 0 {
+            report
+                .hooks
+                .extend(self.post_apply.iter().map(|hook| hook.run(&env, None)));
+        }
+        report
+    }
+
+    /// Puts files back as they were before the chosen apply, current files are backed up first
+    pub fn restore(&self, chosen: &Backup) -> Vec<String> {
+        let mut backup = match self.start_backup() {
+            Ok(backup) => backup,
+            Err(err) => return vec![err],
+        };
+        if let Some(backup) = backup.as_mut() {
+            for file in &chosen.files {
+                if let Err(err) = backup.save(&file.original) {
+                    return vec![err];
+                }
+            }
+        }
+        let mut errors = chosen.restore();
+        errors.extend(self.finish_backup(backup));
+        errors
+    }
+
+    /// Problems that make apply fail or overwrite something unexpected, nothing is written
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let (files, mut problems) = self.expand_files();
+        let sources = files
+            .iter()
+            .map(|x| x.from.as_deref().map(validation::normalize))
+            .collect::<Vec<Option<PathBuf>>>();
+        let mut targets = vec![];
+        for (pos, file) in files.iter().enumerate() {
+            if let Some(from) = file.from.as_ref().filter(|x| !x.exists()) {
+                problems.push(ValidationError::MissingTemplate(from.clone()));
+            }
+            if let Err(reason) = validation::writable(&file.to) {
+                problems.push(ValidationError::Unwritable(file.to.clone(), reason));
+            }
+            let to = validation::normalize(&file.to);
+            let duplicate = ValidationError::DuplicateTarget(file.to.clone());
+            if targets.contains(&to) && !problems.contains(&duplicate) {
+                problems.push(duplicate);
+            }
+            let is_source = sources
+                .iter()
+                .enumerate()
+                .any(|(other, from)| other != pos && from.as_ref() == Some(&to));
+            if is_source {
+                problems.push(ValidationError::TargetIsSource(file.to.clone()));
+            }
+            targets.push(to);
+        }
+        problems
+    }
+
+    /// Diff of every file, nothing is written
+    pub fn diff(&self, palette: &Palette) -> Vec<Result<String, String>> {
+        let (files, errors) = self.expand_files();
+        files
+            .iter()
+            .map(|file| file.diff(palette, self.strict))
+            .chain(errors.into_iter().map(|err| Err(err.to_string())))
+            .collect()
+    }
+
+    /// Lists unknown placeholders, broken templates and roles that are never used
+    pub fn check(&self, palette: &Palette) -> Vec<String> {
+        let (files, errors) = self.expand_files();
+        let mut problems = errors
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>();
+        let mut used = vec![];
+        for file in &files {
+            let data = match fs::read_to_string(file.source()) {
+                Ok(data) => data,
+                Err(err) => {
+                    problems.push(format!("{}: {err}", file.source().display()));
+                    continue;
+                }
+            };
+            if let Some(from) = &file.from {
+                let report = template::check(&data, palette, &file.options(self.strict));
+                problems.extend(
+                    report
+                        .problems
+                        .iter()
+                        .map(|err| format!("{}:{err}", from.display())),
+                );
+                used.extend(report.used);
+            }
+            for rule in &file.rules {
+                let key = Placeholder::key_of(&rule.color);
+                used.push(key.split('.').next().unwrap_or_default().to_string());
+                if let Err(err) = rule.regex() {
+                    problems.push(format!("{}: {err}", file.to.display()));
+                }
+            }
+            // rules are counted on the same output that apply writes,
+            // render errors are already reported by the checks above
+            if !file.rules.is_empty() {
+                if let Ok((_, warnings)) = file.render_checked(palette, self.strict) {
+                    problems.extend(warnings);
+                }
+            }
+            if !file.keys.is_empty() {
+                if let Err(err) = file.get_format() {
+                    problems.push(format!("{}: {err}", file.to.display()));
+                }
+            }
+            for (key, value) in &file.keys {
+                let report = template::check(value, palette, &file.options(self.strict));
+                problems.extend(report.problems.iter().map(|err| {
+                    format!("{}: Key '{key}': {}", file.to.display(), err.message)
+                }));
+                if let Err(err) = structured::split_key(key) {
+                    problems.push(format!("{}: {err}", file.to.display()));
+                }
+                used.extend(report.used);
+            }
+            if let Some(markers) = &file.markers {
+                let region = fs::read_to_string(&file.to)
+                    .map_err(|err| err.to_string())
+                    .and_then(|target| markers.region(&target));
+                if let Err(err) = region {
+                    problems.push(format!("{}: {err}", file.to.display()));
+                }
+            }
+        }
+        for role in &self.roles {
+            if !used.contains(&role.name) {
+                problems.push(format!("Role '{}' is not used in any file", role.name));
+            }
+        }
+        // black or white is the best `.on` can do, some middle colors can't reach the level
+        for (name, color) in palette.roles.get_pairs() {
+            let Ok(color) = RgbColor::from_str(color) else {
+                continue;
+            };
+            let ratio = color.contrast(&color.readable());
+            if ratio < self.contrast.ratio() {
+                problems.push(format!(
+                    "Role '{name}': '{name}.on' has contrast {ratio:.2}:1, below {:?} ({}:1)",
+                    self.contrast,
+                    self.contrast.ratio()
+                ));
+            }
+        }
+        problems
+    }
+
+    pub fn get_roles(&self) -> Vec<Role> {
+        self.roles.clone()
+    }
+
+    pub fn palette(
+        &self,
+        roles: ReplaceColors<String>,
+        colors: Vec<String>,
+        shares: Vec<f64>,
+        mode: Mode,
+    ) -> Palette {
+        let dark = match mode {
+            Mode::Dark => true,
+            Mode::Light => false,
+            Mode::Auto => {
+                let background = match &self.background {
+                    Some(name) => roles.get(name),
+                    None => roles.roles.first().map(|(_, x)| x),
+                };
+                background
+                    .and_then(|x| RgbColor::from_str(x).ok())
+                    .is_some_and(|x| x.is_dark())
+            }
+        };
+        Palette {
+            roles,
+            colors,
+            shares,
+            contrast: self.contrast,
+            dark,
         }
     }
 
+    /// Picks default color for every role, indexes past the end use the last color
+    pub fn default_palette(
+        &self,
+        colors: Vec<String>,
+        shares: Vec<f64>,
+    ) -> Result<Palette, String> {
+        if colors.is_empty() {
+            return Err("Image has no colors to pick roles from".to_string());
+        }
+        let roles = ReplaceColors::new(
+            self.roles
+                .iter()
+                .map(|role| {
+                    let pos = role.index.min(colors.len().saturating_sub(1));
+                    (role.name.clone(), colors[pos].clone())
+                })
+                .collect(),
+        );
+        Ok(self.palette(roles, colors, shares, self.mode))
+    }
+
+    pub fn get_mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    pub fn get_contrast(&self) -> ContrastLevel {
+        self.contrast
+    }
+
+    /// Files with directories and globs expanded, entries that can't be expanded are kept
     pub fn get_files(&self) -> Vec<ReplaceFile> {
-        self.files.clone()
+        self.files
+            .iter()
+            .flat_map(|file| file.expand(&self.dir).unwrap_or_else(|_| vec![file.clone()]))
+            .collect()
+    }
+
+    /// Files with directories and globs expanded, together with entries that couldn't be
+    /// expanded or found nothing
+    fn expand_files(&self) -> (Vec<ReplaceFile>, Vec<ValidationError>) {
+        let mut files = vec![];
+        let mut errors = vec![];
+        for file in &self.files {
+            match file.expand(&self.dir) {
+                Ok(found) if found.is_empty() => {
+                    errors.push(ValidationError::NoFilesFound(file.source().clone()))
+                }
+                Ok(found) => files.extend(found),
+                Err(err) => errors.push(ValidationError::Path(err)),
+            }
+        }
+        (files, errors)
     }
 
     pub fn replace_key(key: String) -> String {
         format!("$[{key}]")
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Three colors, `primary` is #112233
+    pub fn test_palette(dark: bool) -> Palette {
+        let mut palette = Config::default()
+            .default_palette(
+                vec!["#112233".into(), "#445566".into(), "#778899".into()],
+                vec![0.5, 0.3, 0.2],
+            )
+            .unwrap();
+        palette.dark = dark;
+        palette
+    }
+
+    /// Empty directory in the system temp dir, unique for the test `name`
+    pub fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("color-replacer-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn rule_counts_matches() {
+        let palette = test_palette(true);
+        let rule = Rule {
+            pattern: r"^background\s*=\s*(#\w+)".to_string(),
+            color: "primary".to_string(),
+        };
+        let (data, count) = rule.apply("background = #000000\nx = 1\n", &palette).unwrap();
+        assert_eq!(data, "background = #112233\nx = 1\n");
+        assert_eq!(count, 1);
+        assert_eq!(rule.apply("x = 1\n", &palette).unwrap().1, 0);
+
+        let optional = Rule {
+            pattern: r"^bg = (#\w+)?".to_string(),
+            color: "primary".to_string(),
+        };
+        let (data, count) = optional.apply("bg = red\nbg = #000\n", &palette).unwrap();
+        assert_eq!(data, "bg = red\nbg = #112233\n");
+        assert_eq!(count, 1);
+
+        let whole = Rule {
+            pattern: r"#[0-9a-f]{6}".to_string(),
+            color: "secondary".to_string(),
+        };
+        assert_eq!(
+            whole.apply("a #000000 b #ffffff", &palette).unwrap(),
+            ("a #445566 b #445566".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn default_palette_needs_colors() {
+        assert!(Config::default().default_palette(vec![], vec![]).is_err());
+        let palette = Config::default()
+            .default_palette(vec!["#112233".into()], vec![1.0])
+            .unwrap();
+        assert_eq!(palette.roles.get("tertiary").unwrap(), "#112233");
+    }
+
+    #[test]
+    fn check_warns_when_on_misses_contrast() {
+        let cfg = Config {
+            contrast: ContrastLevel::AAA,
+            ..Config::default()
+        };
+        let palette = cfg
+            .default_palette(vec!["#777777".into(), "#000000".into()], vec![0.5, 0.5])
+            .unwrap();
+        let problems = cfg.check(&palette);
+        let contrast = problems
+            .iter()
+            .filter(|x| x.contains(".on' has contrast"))
+            .collect::<Vec<&String>>();
+        assert_eq!(
+            contrast,
+            vec!["Role 'primary': 'primary.on' has contrast 4.69:1, below AAA (7:1)"]
+        );
+    }
+
+    #[test]
+    fn empty_delimiters_fail_loading() {
+        let path = test_dir("delimiters").join("config.json");
+        fs::write(
+            &path,
+            r#"{"warning": {"first_time": false, "text": ""}, "files": [
+                {"from": "a", "to": "b"},
+                {"from": "a", "to": "c", "delimiters": {"open": "", "close": ""}}
+            ]}"#,
+        )
+        .unwrap();
+        let err = Config::from_path(path).err().unwrap();
+        assert!(err.contains("files[1]: delimiters can't be empty"), "{err}");
+    }
+
+    /// Targets of the files `file` expands to, relative to `dir`
+    fn expanded(file: &ReplaceFile, dir: &Path) -> Vec<PathBuf> {
+        let mut targets = file
+            .expand(dir)
+            .unwrap()
+            .iter()
+            .map(|x| x.to.strip_prefix(dir).unwrap().to_path_buf())
+            .collect::<Vec<PathBuf>>();
+        targets.sort();
+        targets
+    }
+
+    #[test]
+    fn expand_walks_directories_and_strips_suffix() {
+        let dir = test_dir("expand-dir");
+        fs::create_dir_all(dir.join("tpl/sub")).unwrap();
+        fs::write(dir.join("tpl/a.conf.tpl"), "").unwrap();
+        fs::write(dir.join("tpl/sub/b.tpl"), "").unwrap();
+        fs::write(dir.join("tpl/c"), "").unwrap();
+        // points back to a parent, walking it would never end
+        std::os::unix::fs::symlink(dir.join("tpl"), dir.join("tpl/sub/loop")).unwrap();
+        let file = ReplaceFile {
+            strip_suffix: Some(".tpl".to_string()),
+            ..ReplaceFile::new("tpl".into(), "out".into())
+        };
+        assert_eq!(
+            expanded(&file, &dir),
+            vec![
+                PathBuf::from("out/a.conf"),
+                PathBuf::from("out/c"),
+                PathBuf::from("out/sub/b")
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn expand_matches_globs() {
+        let dir = test_dir("expand-glob");
+        fs::create_dir_all(dir.join("tpl/sub")).unwrap();
+        fs::create_dir_all(dir.join("[2020]")).unwrap();
+        fs::write(dir.join("tpl/a.tpl"), "").unwrap();
+        fs::write(dir.join("tpl/b.txt"), "").unwrap();
+        fs::write(dir.join("tpl/sub/c.tpl"), "").unwrap();
+        fs::write(dir.join("[2020]/d.tpl"), "").unwrap();
+        let file = ReplaceFile::new("tpl/*.tpl".into(), "out".into());
+        assert_eq!(expanded(&file, &dir), vec![PathBuf::from("out/a.tpl")]);
+        let file = ReplaceFile::new("tpl/**/*.tpl".into(), "out".into());
+        assert_eq!(
+            expanded(&file, &dir),
+            vec![PathBuf::from("out/a.tpl"), PathBuf::from("out/sub/c.tpl")]
+        );
+
+        // brackets of existing paths aren't patterns
+        let file = ReplaceFile::new("[2020]/*.tpl".into(), "out".into());
+        assert_eq!(expanded(&file, &dir), vec![PathBuf::from("out/d.tpl")]);
+        let file = ReplaceFile::new("[2020]/d.tpl".into(), "out".into());
+        assert_eq!(expanded(&file, &dir), vec![PathBuf::from("out")]);
+        let file = ReplaceFile::new("[2020]".into(), "out".into());
+        assert_eq!(expanded(&file, &dir), vec![PathBuf::from("out/d.tpl")]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn empty_glob_is_a_problem() {
+        let dir = test_dir("empty-glob");
+        let cfg = Config {
+            files: vec![ReplaceFile::new("*.tpl".into(), "out".into())],
+            dir: dir.clone(),
+            ..Config::default()
+        };
+        assert_eq!(
+            cfg.validate(),
+            vec![ValidationError::NoFilesFound("*.tpl".into())]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Status names of every file in `report`
+    fn statuses(report: &ApplyReport) -> Vec<&'static str> {
+        report.files.iter().map(|x| x.status.name()).collect()
+    }
+
+    #[test]
+    fn problems_fail_only_their_entries() {
+        let dir = test_dir("problems");
+        fs::write(dir.join("a.tpl"), "a\n").unwrap();
+        let mut cfg = Config {
+            files: vec![
+                ReplaceFile::new("a.tpl".into(), "a".into()),
+                ReplaceFile::new("missing.tpl".into(), "b".into()),
+            ],
+            dir: dir.clone(),
+            backups: 0,
+            ..Config::default()
+        };
+        let report = cfg.process(&test_palette(true));
+        assert_eq!(statuses(&report), vec!["written", "failed"]);
+        assert_eq!(report.summary(), "1 written, 1 failed");
+        assert!(report.errors.is_empty());
+        assert_eq!(fs::read_to_string(dir.join("a")).unwrap(), "a\n");
+        assert!(!dir.join("b").exists());
+
+        // all or nothing
+        fs::remove_file(dir.join("a")).unwrap();
+        cfg.transactional = true;
+        let report = cfg.process(&test_palette(true));
+        assert_eq!(statuses(&report), vec!["skipped", "failed"]);
+        assert!(!dir.join("a").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn transactional_apply_is_all_or_nothing() {
+        let dir = test_dir("transactional");
+        let (out, marker) = (dir.join("out"), dir.join("hook-ran"));
+        fs::write(dir.join("a.tpl"), "new a\n").unwrap();
+        fs::write(dir.join("b.tpl"), "$[include \"missing\"]\n").unwrap();
+        // the hook of the first file only succeeds once the second one is in place too
+        let hook = Hook {
+            command: format!(
+                "test -f '{}' && touch '{}'",
+                out.join("b").display(),
+                marker.display()
+            ),
+            timeout: 5,
+        };
+        let cfg = Config {
+            files: vec![
+                ReplaceFile {
+                    post_apply: vec![hook],
+                    ..ReplaceFile::new("a.tpl".into(), "out/a".into())
+                },
+                ReplaceFile::new("b.tpl".into(), "out/b".into()),
+            ],
+            dir: dir.clone(),
+            backups: 0,
+            transactional: true,
+            ..Config::default()
+        };
+        let undo_dir = std::env::temp_dir().join(format!("color-replacer-{}", std::process::id()));
+
+        // render error, not even directories are created
+        let report = cfg.process(&test_palette(true));
+        assert_eq!(statuses(&report), vec!["skipped", "failed"]);
+        assert!(!out.exists());
+        assert!(!marker.exists());
+
+        // failed rename, the file written before it is put back
+        fs::create_dir_all(&out).unwrap();
+        fs::write(out.join("a"), "old a\n").unwrap();
+        fs::write(out.join("b"), "old b\n").unwrap();
+        fs::write(dir.join("b.tpl"), "new b\n").unwrap();
+        fs::create_dir(temp_path(&out.join("b"))).unwrap();
+        let report = cfg.process(&test_palette(true));
+        assert_eq!(statuses(&report), vec!["skipped", "failed"]);
+        assert_eq!(fs::read_to_string(out.join("a")).unwrap(), "old a\n");
+        assert_eq!(fs::read_to_string(out.join("b")).unwrap(), "old b\n");
+        assert!(!temp_path(&out.join("a")).exists());
+        assert!(!marker.exists());
+        // without backups old files are kept in a temporary directory during apply
+        assert!(!undo_dir.exists());
+
+        // hooks run once every file is in place
+        fs::remove_dir(temp_path(&out.join("b"))).unwrap();
+        let report = cfg.process(&test_palette(true));
+        assert_eq!(statuses(&report), vec!["written", "written"]);
+        assert_eq!(fs::read_to_string(out.join("a")).unwrap(), "new a\n");
+        assert_eq!(fs::read_to_string(out.join("b")).unwrap(), "new b\n");
+        assert!(report.hooks.iter().all(|x| x.success()));
+        assert!(marker.exists());
+        assert!(!undo_dir.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn splice_keeps_end_marker_on_its_own_line() {
+        let target = "a\n# color-replacer:start\nold\n# color-replacer:end\nz\n";
+        let markers = Markers::default();
+        assert_eq!(
+            markers.splice(target, "new").unwrap(),
+            "a\n# color-replacer:start\nnew\n# color-replacer:end\nz\n"
+        );
+        assert_eq!(
+            markers.splice(target, "new\n").unwrap(),
+            "a\n# color-replacer:start\nnew\n# color-replacer:end\nz\n"
+        );
+        assert_eq!(
+            markers.splice(target, "").unwrap(),
+            "a\n# color-replacer:start\n# color-replacer:end\nz\n"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    io::{Read, Write},
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Shell command that is run around applying colors
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Hook {
+    pub command: String,
+    /// Seconds after which the command is killed
+    #[serde(default = "Hook::default_timeout")]
+    pub timeout: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct HookResult {
+    pub command: String,
+    /// `None` if the command couldn't start, timed out or was killed by a signal
+    pub status: Option<i32>,
+    pub timed_out: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Reads pipe in the background, so a full pipe doesn't block the command
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> (Arc<Mutex<Vec<u8>>>, JoinHandle<()>) {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let handle = {
+        let buffer = buffer.clone();
+        thread::spawn(move || {
+            let Some(mut pipe) = pipe else {
+                return;
+            };
+            let mut chunk = [0; 4096];
+            while let Ok(len) = pipe.read(&mut chunk) {
+                if len == 0 {
+                    break;
+                }
+                buffer.lock().unwrap().extend_from_slice(&chunk[..len]);
+            }
+        })
+    };
+    (buffer, handle)
+}
+
+impl Hook {
+    fn default_timeout() -> u64 {
+        10
+    }
+
+    /// `stdin` is written to the command and closed, without it stdin is empty
+    pub fn run(&self, env: &[(String, String)], stdin: Option<&str>) -> HookResult {
+        let mut result = HookResult {
+            command: self.command.clone(),
+            status: None,
+            timed_out: false,
+            stdout: String::new(),
+            stderr: String::new(),
+        };
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .envs(env.iter().cloned())
+            // own process group, so everything the command started can be killed on timeout
+            .process_group(0)
+            .stdin(if stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => {
+                result.stderr = format!("Failed to start: {err}");
+                return result;
+            }
+        };
+        if let (Some(mut pipe), Some(stdin)) = (child.stdin.take(), stdin) {
+            let stdin = stdin.to_string();
+            // commands that don't read stdin shouldn't block
+            thread::spawn(move || {
+                let _ = pipe.write_all(stdin.as_bytes());
+            });
+        }
+        let (stdout, stdout_handle) = read_pipe(child.stdout.take());
+        let (stderr, stderr_handle) = read_pipe(child.stderr.take());
+        let start = Instant::now();
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) => {
+                    result.status = status.code();
+                    break;
+                }
+                Ok(None) if start.elapsed() >= Duration::from_secs(self.timeout) => {
+                    // SAFETY: only sends a signal, the group id is the pid of our own child
+                    unsafe {
+                        libc::kill(-(child.id() as i32), libc::SIGKILL);
+                    }
+                    let _ = child.wait();
+                    result.timed_out = true;
+                    break;
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(10)),
+                Err(err) => {
+                    result.stderr = err.to_string();
+                    return result;
+                }
+            }
+        }
+        // processes started in the background can keep pipes open, they aren't waited for
+        let wait = Instant::now();
+        while !(stdout_handle.is_finished() && stderr_handle.is_finished())
+            && wait.elapsed() < Duration::from_millis(200)
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        result.stdout = String::from_utf8_lossy(&stdout.lock().unwrap()).to_string();
+        result.stderr += &String::from_utf8_lossy(&stderr.lock().unwrap());
+        result
+    }
+}
+
+impl HookResult {
+    pub fn success(&self) -> bool {
+        self.status == Some(0)
+    }
+
+    /// Short description of how the command ended
+    pub fn describe(&self) -> String {
+        match self.status {
+            Some(0) => format!("'{}' succeeded", self.command),
+            Some(code) => format!("'{}' failed with exit code {code}", self.command),
+            None if self.timed_out => format!("'{}' timed out", self.command),
+            None => format!("'{}' failed", self.command),
+        }
+    }
+
+    /// Description followed by everything the command printed
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![self.describe()];
+        lines.extend(self.stdout.lines().map(|x| format!("  {x}")));
+        lines.extend(self.stderr.lines().map(|x| format!("  {x}")));
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::config::tests::test_dir;
+
+    #[test]
+    fn timeout_kills_started_processes() {
+        let dir = test_dir("hook-timeout");
+        let pid_file = dir.join("pid");
+        let hook = Hook {
+            command: format!("sleep 30 & echo $! > '{}'; wait", pid_file.display()),
+            timeout: 1,
+        };
+        let result = hook.run(&[], None);
+        assert!(result.timed_out);
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        // a killed process is either gone or a zombie waiting to be reaped
+        let stat =
+            std::fs::read_to_string(format!("/proc/{}/stat", pid.trim())).unwrap_or_default();
+        assert!(stat.is_empty() || stat.contains(") Z "), "{stat}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1 +1,7 @@
+pub mod backup;
+pub mod color;
 pub mod config;
+pub mod hooks;
+pub mod structured;
+pub mod template;
+pub mod validation;
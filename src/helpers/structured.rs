@@ -0,0 +1,905 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    Json,
+    Toml,
+    Yaml,
+    Ini,
+}
+
+/// Splits `a.b."c.d"` into `a`, `b` and `c.d`
+pub fn split_key(key: &str) -> Result<Vec<String>, String> {
+    let mut parts = vec![];
+    let mut part = String::new();
+    let mut quoted = false;
+    for ch in key.chars() {
+        match ch {
+            '"' => quoted = !quoted,
+            '.' if !quoted => parts.push(std::mem::take(&mut part)),
+            _ => part.push(ch),
+        }
+    }
+    if quoted {
+        return Err(format!("Key '{key}' has an unclosed quote"));
+    }
+    parts.push(part);
+    if parts.iter().any(|x| x.is_empty()) {
+        return Err(format!("Key '{key}' has an empty part"));
+    }
+    Ok(parts)
+}
+
+impl FileFormat {
+    /// Guesses format by extension of the file
+    pub fn from_path(path: &Path) -> Option<FileFormat> {
+        match path.extension()?.to_str()? {
+            "json" | "jsonc" => Some(FileFormat::Json),
+            "toml" => Some(FileFormat::Toml),
+            "yaml" | "yml" => Some(FileFormat::Yaml),
+            "ini" => Some(FileFormat::Ini),
+            _ => None,
+        }
+    }
+
+    /// Sets string `value` at `path`, creating missing parents
+    pub fn set(&self, data: &str, path: &[String], value: &str) -> Result<String, String> {
+        match self {
+            FileFormat::Json => json_set(data, path, value),
+            FileFormat::Toml => toml_set(data, path, value),
+            FileFormat::Yaml => yaml_set(data, path, value),
+            FileFormat::Ini => ini_set(data, path, value),
+        }
+    }
+}
+
+fn toml_set(data: &str, path: &[String], value: &str) -> Result<String, String> {
+    let mut doc = data
+        .parse::<toml_edit::DocumentMut>()
+        .map_err(|err| err.to_string())?;
+    let (last, parents) = path.split_last().ok_or("Empty key")?;
+    let mut table = doc.as_table_mut() as &mut dyn toml_edit::TableLike;
+    for part in parents {
+        // only the last table gets a header, `[a.b]` instead of `[a]` and `[a.b]`
+        let mut parent = toml_edit::Table::new();
+        parent.set_implicit(true);
+        let item = table.entry(part).or_insert(toml_edit::Item::Table(parent));
+        table = item
+            .as_table_like_mut()
+            .ok_or(format!("'{part}' is not a table"))?;
+    }
+    match table.get_mut(last) {
+        Some(toml_edit::Item::Value(old)) if old.is_inline_table() => {
+            Err(format!("'{last}' is a table"))
+        }
+        Some(toml_edit::Item::Value(old)) if old.is_array() => Err(format!("'{last}' is an array")),
+        // keeps comments and spacing around the old value and literal 'quotes'
+        Some(toml_edit::Item::Value(old)) => {
+            let decor = old.decor().clone();
+            let literal = match &*old {
+                toml_edit::Value::String(old) => old
+                    .as_repr()
+                    .and_then(|x| x.as_raw().as_str())
+                    .is_some_and(|x| x.starts_with('\'') && !x.starts_with("'''")),
+                _ => false,
+            };
+            *old = match format!("'{value}'").parse::<toml_edit::Value>() {
+                Ok(new) if literal && !value.contains(['\'', '\n', '\r']) => new,
+                _ => value.into(),
+            };
+            *old.decor_mut() = decor;
+            Ok(())
+        }
+        Some(toml_edit::Item::Table(_)) => Err(format!("'{last}' is a table")),
+        Some(toml_edit::Item::ArrayOfTables(_)) => Err(format!("'{last}' is an array of tables")),
+        Some(toml_edit::Item::None) | None => {
+            table.insert(last, toml_edit::value(value));
+            Ok(())
+        }
+    }?;
+    let out = doc.to_string();
+    Ok(join_lines(data, out.lines().map(String::from).collect()))
+}
+
+/// Key of a `key: value` line in a block mapping
+struct YamlKey {
+    indent: usize,
+    key: String,
+    /// Position right after the colon
+    value_start: usize,
+}
+
+/// Lines that don't start a new key and don't end a block
+fn yaml_skipped(line: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.is_empty() || trimmed.starts_with('#') || trimmed == "---"
+}
+
+fn yaml_key(line: &str) -> Option<YamlKey> {
+    if yaml_skipped(line) {
+        return None;
+    }
+    let trimmed = line.trim_start();
+    let indent = line.len() - trimmed.len();
+    let (key, rest) = match trimmed.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let end = trimmed[1..].find(quote)? + 1;
+            (trimmed[1..end].to_string(), &trimmed[end + 1..])
+        }
+        _ => {
+            let end = trimmed.match_indices(':').map(|(pos, _)| pos).find(|pos| {
+                trimmed[pos + 1..].is_empty() || trimmed[pos + 1..].starts_with([' ', '\t'])
+            })?;
+            (trimmed[..end].trim_end().to_string(), &trimmed[end..])
+        }
+    };
+    let rest = rest.trim_start_matches([' ', '\t']);
+    rest.strip_prefix(':').map(|after| YamlKey {
+        indent,
+        key,
+        value_start: line.len() - after.len(),
+    })
+}
+
+/// Plain scalars that would be read as something else than the same string
+fn yaml_needs_quotes(value: &str) -> bool {
+    value.is_empty()
+        || value.starts_with([
+            '#', '&', '*', '!', '|', '>', '\'', '"', '%', '@', '`', '{', '[', ',', '-', '?', ':',
+        ])
+        || value.contains(": ")
+        || value.contains(" #")
+        || value.ends_with(':')
+        || value.trim() != value
+        || ["true", "false", "null", "yes", "no", "on", "off", "~"]
+            .contains(&value.to_lowercase().as_str())
+        || value.parse::<f64>().is_ok()
+}
+
+/// Replaces the value after the colon, the old quoting style and a trailing comment are kept
+fn yaml_replace(line: &str, entry: &YamlKey, value: &str) -> Result<String, String> {
+    let rest = &line[entry.value_start..];
+    let old = rest.trim_start_matches([' ', '\t']);
+    let spacing = &rest[..rest.len() - old.len()];
+    let (old_len, quote) = match old.chars().next() {
+        Some('\'') => {
+            // '' is an escaped quote inside single quotes
+            let mut end = 1;
+            loop {
+                match old[end..].find('\'') {
+                    Some(pos) if old[end + pos + 1..].starts_with('\'') => end += pos + 2,
+                    Some(pos) => break (end + pos + 1, Some('\'')),
+                    None => return Err(format!("'{}' has an unclosed quote", entry.key)),
+                }
+            }
+        }
+        Some('"') => {
+            let mut escaped = false;
+            let end = old[1..].char_indices().find_map(|(pos, ch)| {
+                let end = !escaped && ch == '"';
+                escaped = !escaped && ch == '\\';
+                end.then_some(pos + 2)
+            });
+            (
+                end.ok_or(format!("'{}' has an unclosed quote", entry.key))?,
+                Some('"'),
+            )
+        }
+        Some('|' | '>' | '&' | '*' | '{' | '[') => {
+            return Err(format!(
+                "'{}' has a value that can't be replaced",
+                entry.key
+            ))
+        }
+        _ => (
+            old.find(" #").or(old.find("\t#")).unwrap_or(old.len()),
+            None,
+        ),
+    };
+    // spaces before a comment stay with the comment
+    let old_value = old[..old_len].trim_end();
+    let new = match quote {
+        Some('"') => serde_json::to_string(value).map_err(|err| err.to_string())?,
+        Some(_) => format!("'{}'", value.replace('\'', "''")),
+        None if yaml_needs_quotes(value) => format!("'{}'", value.replace('\'', "''")),
+        None => value.to_string(),
+    };
+    let spacing = if spacing.is_empty() { " " } else { spacing };
+    Ok(format!(
+        "{}{spacing}{new}{}",
+        &line[..entry.value_start],
+        &old[old_value.len()..]
+    ))
+}
+
+/// Edits only the line of the key, so comments and formatting of the file are kept.
+/// Block mappings are supported, flow mappings, lists and multi-line values are not
+fn yaml_set(data: &str, path: &[String], value: &str) -> Result<String, String> {
+    let mut lines = data.lines().map(String::from).collect::<Vec<String>>();
+    let (mut start, mut end) = (0, lines.len());
+    let mut parent_indent = None;
+    let mut depth = 0;
+    while depth < path.len() {
+        let first = lines[start..end].iter().find(|x| !yaml_skipped(x));
+        if first.is_some_and(|x| x.trim_start().starts_with('-')) {
+            return Err(format!("Parent of '{}' is not a mapping", path[depth]));
+        }
+        let children = (start..end)
+            .filter_map(|pos| yaml_key(&lines[pos]).map(|key| (pos, key)))
+            .collect::<Vec<(usize, YamlKey)>>();
+        let indent = children.iter().map(|(_, x)| x.indent).min();
+        let children = children
+            .into_iter()
+            .filter(|(_, x)| Some(x.indent) == indent)
+            .collect::<Vec<(usize, YamlKey)>>();
+        // keys can contain dots themselves, like `workbench.colorCustomizations`
+        let found = (depth + 1..=path.len()).rev().find_map(|next| {
+            let key = path[depth..next].join(".");
+            children
+                .iter()
+                .find(|(_, x)| x.key == key)
+                .map(|(pos, entry)| (*pos, entry, next))
+        });
+        let Some((pos, entry, next)) = found else {
+            // missing keys are created as nested mappings at the end of the block
+            let indent = indent.unwrap_or(parent_indent.map_or(0, |x| x + 2));
+            let insert_at = (start..end)
+                .rev()
+                .find(|pos| !yaml_skipped(&lines[*pos]))
+                .map_or(start, |x| x + 1);
+            let new = path[depth..].iter().enumerate().map(|(level, key)| {
+                let key = if yaml_needs_quotes(key) {
+                    format!("'{}'", key.replace('\'', "''"))
+                } else {
+                    key.clone()
+                };
+                let indent = " ".repeat(indent + level * 2);
+                if depth + level + 1 == path.len() {
+                    let value = if yaml_needs_quotes(value) {
+                        format!("'{}'", value.replace('\'', "''"))
+                    } else {
+                        value.to_string()
+                    };
+                    format!("{indent}{key}: {value}")
+                } else {
+                    format!("{indent}{key}:")
+                }
+            });
+            lines.splice(insert_at..insert_at, new.collect::<Vec<String>>());
+            return Ok(join_lines(data, lines));
+        };
+        if next == path.len() {
+            // a key without a value on its line starts a nested block
+            let nested = (pos + 1..end)
+                .find(|x| !yaml_skipped(&lines[*x]))
+                .filter(|x| {
+                    let line = &lines[*x];
+                    let indent = line.len() - line.trim_start().len();
+                    indent > entry.indent
+                        || indent == entry.indent && line.trim_start().starts_with('-')
+                });
+            let inline = lines[pos][entry.value_start..].trim();
+            if let Some(nested) = nested.filter(|_| inline.is_empty() || inline.starts_with('#')) {
+                return Err(if lines[nested].trim_start().starts_with('-') {
+                    format!("'{}' is a list", entry.key)
+                } else {
+                    format!("'{}' is a mapping", entry.key)
+                });
+            }
+            lines[pos] = yaml_replace(&lines[pos], entry, value)?;
+            return Ok(join_lines(data, lines));
+        }
+        let inline = lines[pos][entry.value_start..].trim();
+        if !inline.is_empty() && !inline.starts_with('#') {
+            return Err(format!("'{}' is not a mapping", entry.key));
+        }
+        let block_indent = entry.indent;
+        start = pos + 1;
+        end = (start..end)
+            .find(|x| {
+                let line = &lines[*x];
+                !yaml_skipped(line) && line.len() - line.trim_start().len() <= block_indent
+            })
+            .unwrap_or(end);
+        // lists can start at the same indent as their key
+        if lines.get(end).is_some_and(|x| {
+            x.len() - x.trim_start().len() == block_indent && x.trim_start().starts_with('-')
+        }) {
+            return Err(format!("'{}' is not a mapping", entry.key));
+        }
+        parent_indent = Some(block_indent);
+        depth = next;
+    }
+    Err("Empty key".to_string())
+}
+
+/// Keys before any section are used without a section name
+fn ini_set(data: &str, path: &[String], value: &str) -> Result<String, String> {
+    let (section, key) = match path {
+        [key] => (None, key),
+        [section, key] => (Some(section.as_str()), key),
+        _ => return Err("Ini keys should look like 'section.key' or 'key'".to_string()),
+    };
+    let mut lines = data.lines().map(String::from).collect::<Vec<String>>();
+    let mut current = None;
+    // line after the last line of the section, where a new key is inserted
+    let mut insert_at = if section.is_none() { Some(0) } else { None };
+    for (pos, line) in lines.iter_mut().enumerate() {
+        let trimmed = line.trim();
+        if let Some(name) = trimmed.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+            current = Some(name.trim().to_string());
+            if current.as_deref() == section {
+                insert_at = Some(pos + 1);
+            }
+            continue;
+        }
+        if current.as_deref() != section {
+            continue;
+        }
+        if trimmed.is_empty() || trimmed.starts_with([';', '#']) {
+            continue;
+        }
+        insert_at = Some(pos + 1);
+        let Some(separator) = line.find(['=', ':']) else {
+            continue;
+        };
+        if line[..separator].trim() != key {
+            continue;
+        }
+        let spaces = line[separator + 1..].len() - line[separator + 1..].trim_start().len();
+        line.truncate(separator + 1 + spaces);
+        if spaces == 0 && line.ends_with('=') && line[..separator].ends_with(' ') {
+            line.push(' ');
+        }
+        line.push_str(value);
+        return Ok(join_lines(data, lines));
+    }
+    match (insert_at, section) {
+        (Some(pos), _) => lines.insert(pos, format!("{key} = {value}")),
+        (None, Some(section)) => {
+            if lines.last().is_some_and(|x| !x.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(format!("[{section}]"));
+            lines.push(format!("{key} = {value}"));
+        }
+        (None, None) => lines.push(format!("{key} = {value}")),
+    }
+    Ok(join_lines(data, lines))
+}
+
+/// Joins lines back keeping line endings and the final newline of the original file
+fn join_lines(data: &str, lines: Vec<String>) -> String {
+    let newline = if data.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut out = lines.join(newline);
+    if data.is_empty() || data.ends_with('\n') {
+        out.push_str(newline);
+    }
+    out
+}
+
+/// Position of a key and its value inside a json object
+struct Entry {
+    key: String,
+    key_start: usize,
+    value_end: usize,
+    value_start: usize,
+}
+
+/// Walks json text without building a tree, so comments and formatting are kept
+struct JsonScanner<'a> {
+    data: &'a str,
+    pos: usize,
+}
+
+impl<'a> JsonScanner<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.data.as_bytes().get(self.pos).copied()
+    }
+
+    fn error(&self, message: &str) -> String {
+        let before = &self.data[..self.pos.min(self.data.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |x| x + 1) + 1;
+        format!("{line}:{column}: {message}")
+    }
+
+    /// Skips whitespace and comments
+    fn skip_space(&mut self) {
+        loop {
+            let rest = &self.data[self.pos..];
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if trimmed.starts_with("/*") {
+                self.pos += trimmed.find("*/").map_or(trimmed.len(), |x| x + 2);
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn expect(&mut self, ch: u8) -> Result<(), String> {
+        self.skip_space();
+        if self.peek() != Some(ch) {
+            return Err(self.error(&format!("Expected '{}'", ch as char)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        self.expect(b'"')?;
+        while let Some(ch) = self.peek() {
+            self.pos += match ch {
+                b'\\' => 2,
+                b'"' => {
+                    self.pos += 1;
+                    return serde_json::from_str(&self.data[start..self.pos])
+                        .map_err(|err| self.error(&err.to_string()));
+                }
+                _ => 1,
+            };
+        }
+        Err(self.error("String is never closed"))
+    }
+
+    /// Reads object that starts at the current position and moves past it,
+    /// returns its entries and position of the closing brace
+    fn object(&mut self) -> Result<(Vec<Entry>, usize), String> {
+        self.expect(b'{')?;
+        let mut entries = vec![];
+        loop {
+            self.skip_space();
+            if self.peek() == Some(b'}') {
+                self.pos += 1;
+                return Ok((entries, self.pos - 1));
+            }
+            let key_start = self.pos;
+            let key = self.string()?;
+            self.expect(b':')?;
+            self.skip_space();
+            let value_start = self.pos;
+            self.value()?;
+            entries.push(Entry {
+                key,
+                key_start,
+                value_start,
+                value_end: self.pos,
+            });
+            self.skip_space();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {}
+                _ => return Err(self.error("Expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<(), String> {
+        self.skip_space();
+        match self.peek() {
+            Some(b'{') => self.object().map(|_| ()),
+            Some(b'[') => {
+                self.pos += 1;
+                loop {
+                    self.skip_space();
+                    if self.peek() == Some(b']') {
+                        self.pos += 1;
+                        return Ok(());
+                    }
+                    self.value()?;
+                    self.skip_space();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {}
+                        _ => return Err(self.error("Expected ',' or ']'")),
+                    }
+                }
+            }
+            Some(b'"') => self.string().map(|_| ()),
+            Some(_) => {
+                let rest = &self.data[self.pos..];
+                let len = rest
+                    .find(|ch: char| ch.is_whitespace() || ",]}/".contains(ch))
+                    .unwrap_or(rest.len());
+                if len == 0 {
+                    return Err(self.error("Expected a value"));
+                }
+                self.pos += len;
+                Ok(())
+            }
+            None => Err(self.error("Unexpected end of file")),
+        }
+    }
+}
+
+/// Indentation of the line that contains `pos`
+fn indent_at(data: &str, pos: usize) -> &str {
+    let start = data[..pos].rfind('\n').map_or(0, |x| x + 1);
+    let line = &data[start..];
+    &line[..line.len() - line.trim_start().len()]
+}
+
+fn json_set(data: &str, path: &[String], value: &str) -> Result<String, String> {
+    let data = if data.trim().is_empty() { "{}\n" } else { data };
+    let value = serde_json::to_string(value).map_err(|err| err.to_string())?;
+    let newline = if data.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut scanner = JsonScanner { data, pos: 0 };
+    scanner.skip_space();
+    let mut depth = 0;
+    let mut key = String::new();
+    while let Some(part) = path.get(depth) {
+        let object_start = scanner.pos;
+        if scanner.peek() != Some(b'{') {
+            return Err(scanner.error(&format!("Parent of '{part}' is not an object")));
+        }
+        let (entries, end) = scanner.object()?;
+        // keys can contain dots themselves, like `workbench.colorCustomizations`
+        let found = (depth + 1..=path.len()).rev().find_map(|next| {
+            let key = path[depth..next].join(".");
+            entries
+                .iter()
+                .find(|x| x.key == key)
+                .map(|entry| (entry.value_start, next))
+        });
+        if let Some((value_start, next)) = found {
+            scanner.pos = value_start;
+            key = path[depth..next].join(".");
+            depth = next;
+            continue;
+        }
+        // missing keys are created as nested objects
+        let nested = path[depth..].iter().rev().fold(value, |inner, key| {
+            format!("{{{}: {inner}}}", serde_json::Value::from(key.as_str()))
+        });
+        let entry = &nested[1..nested.len() - 1];
+        let out = match entries.last() {
+            Some(last) => {
+                let separator = if data[object_start..end].contains('\n') {
+                    format!(",{newline}{}", indent_at(data, last.key_start))
+                } else {
+                    ", ".to_string()
+                };
+                format!(
+                    "{}{separator}{entry}{}",
+                    &data[..last.value_end],
+                    &data[last.value_end..]
+                )
+            }
+            None => {
+                let indent = indent_at(data, object_start);
+                format!(
+                    "{}{{{newline}{indent}  {entry}{newline}{indent}}}{}",
+                    &data[..object_start],
+                    &data[end + 1..]
+                )
+            }
+        };
+        return Ok(out);
+    }
+    let start = scanner.pos;
+    match scanner.peek() {
+        Some(b'{') => return Err(scanner.error(&format!("'{key}' is an object"))),
+        Some(b'[') => return Err(scanner.error(&format!("'{key}' is an array"))),
+        _ => {}
+    }
+    scanner.value()?;
+    Ok(format!("{}{value}{}", &data[..start], &data[scanner.pos..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(format: FileFormat, data: &str, key: &str, value: &str) -> Result<String, String> {
+        format.set(data, &split_key(key).unwrap(), value)
+    }
+
+    #[test]
+    fn json_keeps_comments() {
+        let data = "{\n  // theme\n  \"bg\": \"#000\", /* old */\n  \"fg\": \"#fff\"\n}\n";
+        assert_eq!(
+            set(FileFormat::Json, data, "bg", "#112233").unwrap(),
+            "{\n  // theme\n  \"bg\": \"#112233\", /* old */\n  \"fg\": \"#fff\"\n}\n"
+        );
+    }
+
+    #[test]
+    fn json_trailing_commas() {
+        let data = "{\n  \"a\": {\"b\": 1,},\n  \"c\": [1, 2,],\n}\n";
+        assert_eq!(
+            set(FileFormat::Json, data, "a.b", "x").unwrap(),
+            "{\n  \"a\": {\"b\": \"x\",},\n  \"c\": [1, 2,],\n}\n"
+        );
+        assert_eq!(
+            set(FileFormat::Json, data, "d", "x").unwrap(),
+            "{\n  \"a\": {\"b\": 1,},\n  \"c\": [1, 2,],\n  \"d\": \"x\",\n}\n"
+        );
+    }
+
+    #[test]
+    fn json_dotted_keys() {
+        let data = "{\"workbench.colorCustomizations\": {\"editor.background\": \"#000\"}}";
+        assert_eq!(
+            set(
+                FileFormat::Json,
+                data,
+                "workbench.colorCustomizations.\"editor.background\"",
+                "#fff"
+            )
+            .unwrap(),
+            "{\"workbench.colorCustomizations\": {\"editor.background\": \"#fff\"}}"
+        );
+        assert_eq!(
+            set(
+                FileFormat::Json,
+                data,
+                "workbench.colorCustomizations.editor.background",
+                "#fff"
+            )
+            .unwrap(),
+            "{\"workbench.colorCustomizations\": {\"editor.background\": \"#fff\"}}"
+        );
+    }
+
+    #[test]
+    fn json_inserts_missing_parents() {
+        let data = "{\n  \"a\": 1\n}\n";
+        assert_eq!(
+            set(FileFormat::Json, data, "b.c", "x").unwrap(),
+            "{\n  \"a\": 1,\n  \"b\": {\"c\": \"x\"}\n}\n"
+        );
+        assert_eq!(
+            set(FileFormat::Json, "{}", "a.b", "x").unwrap(),
+            "{\n  \"a\": {\"b\": \"x\"}\n}"
+        );
+        assert_eq!(
+            set(FileFormat::Json, "", "a", "x").unwrap(),
+            "{\n  \"a\": \"x\"\n}\n"
+        );
+        assert!(set(FileFormat::Json, "{\"a\": 1}", "a.b", "x").is_err());
+    }
+
+    #[test]
+    fn json_crlf_and_final_newline() {
+        let data = "{\r\n  \"a\": 1\r\n}";
+        assert_eq!(
+            set(FileFormat::Json, data, "a", "x").unwrap(),
+            "{\r\n  \"a\": \"x\"\r\n}"
+        );
+        assert_eq!(
+            set(FileFormat::Json, data, "b", "x").unwrap(),
+            "{\r\n  \"a\": 1,\r\n  \"b\": \"x\"\r\n}"
+        );
+    }
+
+    #[test]
+    fn json_errors_have_position() {
+        let err = set(FileFormat::Json, "{\n  \"a\" 1\n}", "a", "x").unwrap_err();
+        assert!(err.starts_with("2:7:"), "{err}");
+    }
+
+    #[test]
+    fn toml_keeps_comments_and_quotes() {
+        let data = "# top\nx = 1\n\n[colors] # main\nbg = '#000' # old\nfg = \"#fff\"\n";
+        assert_eq!(
+            set(FileFormat::Toml, data, "colors.bg", "#112233").unwrap(),
+            "# top\nx = 1\n\n[colors] # main\nbg = '#112233' # old\nfg = \"#fff\"\n"
+        );
+        assert_eq!(
+            set(FileFormat::Toml, data, "colors.fg", "#112233").unwrap(),
+            "# top\nx = 1\n\n[colors] # main\nbg = '#000' # old\nfg = \"#112233\"\n"
+        );
+        // literal strings can't hold a quote
+        assert_eq!(
+            set(FileFormat::Toml, "a = 'x'\n", "a", "it's").unwrap(),
+            "a = \"it's\"\n"
+        );
+    }
+
+    #[test]
+    fn toml_inserts_missing_parents() {
+        assert_eq!(
+            set(FileFormat::Toml, "x = 1\n", "a.b.c", "#fff").unwrap(),
+            "x = 1\n\n[a.b]\nc = \"#fff\"\n"
+        );
+        assert_eq!(
+            set(FileFormat::Toml, "[colors]\nbg = 1\n", "colors.fg", "#fff").unwrap(),
+            "[colors]\nbg = 1\nfg = \"#fff\"\n"
+        );
+        assert_eq!(
+            set(FileFormat::Toml, "", "a", "#fff").unwrap(),
+            "a = \"#fff\"\n"
+        );
+        assert!(set(FileFormat::Toml, "a = 1\n", "a.b", "#fff").is_err());
+    }
+
+    #[test]
+    fn toml_dotted_keys_crlf_and_final_newline() {
+        assert_eq!(
+            set(
+                FileFormat::Toml,
+                "\"editor.bg\" = 'x'\r\nz = 1",
+                "\"editor.bg\"",
+                "#fff"
+            )
+            .unwrap(),
+            "\"editor.bg\" = '#fff'\r\nz = 1"
+        );
+        assert_eq!(
+            set(FileFormat::Toml, "[a]\r\nbg = '#000'", "a.fg", "#fff").unwrap(),
+            "[a]\r\nbg = '#000'\r\nfg = \"#fff\""
+        );
+    }
+
+    #[test]
+    fn tables_are_not_replaced() {
+        let toml = "[colors.sub]\na = 1\n";
+        assert_eq!(
+            set(FileFormat::Toml, toml, "colors.sub", "#fff").unwrap_err(),
+            "'sub' is a table"
+        );
+        assert!(set(FileFormat::Toml, "a = { b = 1 }\n", "a", "x").is_err());
+        assert!(set(FileFormat::Toml, "a = [1]\n", "a", "x").is_err());
+        assert!(set(FileFormat::Toml, "[[a]]\nb = 1\n", "a", "x").is_err());
+
+        let err = set(FileFormat::Json, "{\"a\": {\"b\": 1}}", "a", "x").unwrap_err();
+        assert_eq!(err, "1:7: 'a' is an object");
+        assert!(set(FileFormat::Json, "{\"a\": [1]}", "a", "x").is_err());
+
+        let yaml = "a:\n  b: 1\n";
+        assert_eq!(
+            set(FileFormat::Yaml, yaml, "a", "x").unwrap_err(),
+            "'a' is a mapping"
+        );
+        let yaml = "a: # colors\n\n  b: 1\n";
+        assert_eq!(
+            set(FileFormat::Yaml, yaml, "a", "x").unwrap_err(),
+            "'a' is a mapping"
+        );
+        let yaml = "a:\n- 1\nb: 2\n";
+        assert_eq!(
+            set(FileFormat::Yaml, yaml, "a", "x").unwrap_err(),
+            "'a' is a list"
+        );
+        // a key without a value is null and can be set
+        assert_eq!(
+            set(FileFormat::Yaml, "a:\nb: 1\n", "a", "x").unwrap(),
+            "a: x\nb: 1\n"
+        );
+    }
+
+    #[test]
+    fn ini_keeps_comments() {
+        let data = "; colors\n[theme]\n# background\nbg = #000\nfg=#fff\n";
+        assert_eq!(
+            set(FileFormat::Ini, data, "theme.bg", "#112233").unwrap(),
+            "; colors\n[theme]\n# background\nbg = #112233\nfg=#fff\n"
+        );
+        assert_eq!(
+            set(FileFormat::Ini, data, "theme.fg", "#112233").unwrap(),
+            "; colors\n[theme]\n# background\nbg = #000\nfg=#112233\n"
+        );
+    }
+
+    #[test]
+    fn ini_inserts_missing_keys_and_sections() {
+        let data = "top = 1\n\n[theme]\nbg = #000\n\n[other]\nx = 1\n";
+        assert_eq!(
+            set(FileFormat::Ini, data, "theme.fg", "#fff").unwrap(),
+            "top = 1\n\n[theme]\nbg = #000\nfg = #fff\n\n[other]\nx = 1\n"
+        );
+        assert_eq!(
+            set(FileFormat::Ini, data, "new.fg", "#fff").unwrap(),
+            "top = 1\n\n[theme]\nbg = #000\n\n[other]\nx = 1\n\n[new]\nfg = #fff\n"
+        );
+        assert_eq!(
+            set(FileFormat::Ini, data, "key", "v").unwrap(),
+            "top = 1\nkey = v\n\n[theme]\nbg = #000\n\n[other]\nx = 1\n"
+        );
+        assert!(set(FileFormat::Ini, data, "a.b.c", "v").is_err());
+    }
+
+    #[test]
+    fn ini_crlf_and_final_newline() {
+        let data = "[theme]\r\nbg = #000";
+        assert_eq!(
+            set(FileFormat::Ini, data, "theme.bg", "#fff").unwrap(),
+            "[theme]\r\nbg = #fff"
+        );
+        assert_eq!(
+            set(FileFormat::Ini, data, "theme.fg", "#fff").unwrap(),
+            "[theme]\r\nbg = #000\r\nfg = #fff"
+        );
+    }
+
+    #[test]
+    fn split_key_handles_quotes() {
+        assert_eq!(split_key("a.\"b.c\".d").unwrap(), vec!["a", "b.c", "d"]);
+        assert!(split_key("a.\"b").is_err());
+        assert!(split_key("a..b").is_err());
+    }
+
+    #[test]
+    fn yaml_keeps_comments_and_quotes() {
+        let data = "# comment\ncolors:\n  bg: '#000' # keep\n  fg: \"#fff\"\n";
+        assert_eq!(
+            set(FileFormat::Yaml, data, "colors.bg", "#112233").unwrap(),
+            "# comment\ncolors:\n  bg: '#112233' # keep\n  fg: \"#fff\"\n"
+        );
+        assert_eq!(
+            set(FileFormat::Yaml, data, "colors.fg", "#112233").unwrap(),
+            "# comment\ncolors:\n  bg: '#000' # keep\n  fg: \"#112233\"\n"
+        );
+    }
+
+    #[test]
+    fn yaml_quotes_plain_values_when_needed() {
+        let data = "bg: black\nfg: white # text\n";
+        assert_eq!(
+            set(FileFormat::Yaml, data, "bg", "#112233").unwrap(),
+            "bg: '#112233'\nfg: white # text\n"
+        );
+        assert_eq!(
+            set(FileFormat::Yaml, data, "fg", "rgb(1, 2, 3)").unwrap(),
+            "bg: black\nfg: rgb(1, 2, 3) # text\n"
+        );
+        assert_eq!(
+            set(FileFormat::Yaml, data, "bg", "112233").unwrap(),
+            "bg: '112233'\nfg: white # text\n"
+        );
+    }
+
+    #[test]
+    fn yaml_inserts_missing_parents() {
+        let data = "colors:\n    bg: '#000'\n\nother: 1\n";
+        assert_eq!(
+            set(FileFormat::Yaml, data, "colors.fg", "#fff").unwrap(),
+            "colors:\n    bg: '#000'\n    fg: '#fff'\n\nother: 1\n"
+        );
+        assert_eq!(
+            set(FileFormat::Yaml, data, "theme.colors.bg", "#fff").unwrap(),
+            "colors:\n    bg: '#000'\n\nother: 1\ntheme:\n  colors:\n    bg: '#fff'\n"
+        );
+        assert_eq!(
+            set(FileFormat::Yaml, "", "bg", "#fff").unwrap(),
+            "bg: '#fff'\n"
+        );
+    }
+
+    #[test]
+    fn yaml_nested_blocks_end_at_dedent() {
+        let data = "a:\n  b:\n    c: 1\n  # note\n  d: 2\ne: 3\n";
+        assert_eq!(
+            set(FileFormat::Yaml, data, "a.d", "x").unwrap(),
+            "a:\n  b:\n    c: 1\n  # note\n  d: x\ne: 3\n"
+        );
+        assert_eq!(
+            set(FileFormat::Yaml, data, "a.b.f", "x").unwrap(),
+            "a:\n  b:\n    c: 1\n    f: x\n  # note\n  d: 2\ne: 3\n"
+        );
+    }
+
+    #[test]
+    fn yaml_dotted_keys_crlf_and_final_newline() {
+        let data = "\"editor.fg\": old\r\nx: 1";
+        assert_eq!(
+            set(FileFormat::Yaml, data, "editor.fg", "new").unwrap(),
+            "\"editor.fg\": new\r\nx: 1"
+        );
+    }
+
+    #[test]
+    fn yaml_rejects_unsupported_values() {
+        assert!(set(FileFormat::Yaml, "a:\n  - 1\n", "a.b", "x").is_err());
+        assert!(set(FileFormat::Yaml, "a:\n- 1\n", "a.b", "x").is_err());
+        assert!(set(FileFormat::Yaml, "a: {b: 1}\n", "a.b", "x").is_err());
+        assert!(set(FileFormat::Yaml, "a: |\n  text\n", "a", "x").is_err());
+    }
+}
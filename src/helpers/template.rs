@@ -0,0 +1,1025 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    color::{ColorFormat, RgbColor},
+    config::Palette,
+};
+
+pub const OPEN: &str = "$[";
+pub const CLOSE: &str = "]";
+/// Written before opening delimiter to keep it as is
+pub const ESCAPE: char = '\\';
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Delimiters {
+    pub open: String,
+    pub close: String,
+}
+
+#[derive(Debug)]
+pub struct TemplateError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+/// Parsed content of `$[key|filter(args):format]`
+pub struct Placeholder {
+    pub key: String,
+    pub filters: Vec<Filter>,
+    pub format: ColorFormat,
+}
+
+pub enum Filter {
+    Lighten(f64),
+    Darken(f64),
+    Saturate(f64),
+    Desaturate(f64),
+    Hue(f64),
+    Mix(String, f64),
+    Invert,
+    Alpha(f64),
+}
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl TemplateError {
+    pub fn at(data: &str, offset: usize, message: String) -> Self {
+        let before = &data[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before
+            .rfind('\n')
+            .map(|x| before[x + 1..].chars().count())
+            .unwrap_or(before.chars().count())
+            + 1;
+        Self {
+            line,
+            column,
+            message,
+        }
+    }
+}
+
+impl Placeholder {
+    /// Returns the key part of the placeholder without parsing the rest of it
+    pub fn key_of(content: &str) -> &str {
+        content.split([':', '|']).next().unwrap_or_default().trim()
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let (content, format) = match content.rsplit_once(':') {
+            Some((content, format)) if !format.contains(')') => {
+                (content, ColorFormat::from_str(format.trim())?)
+            }
+            _ => (content, ColorFormat::Hex),
+        };
+        let mut parts = split_top_level(content, '|').into_iter();
+        let key = parts.next().unwrap_or_default().trim().to_string();
+        let filters = parts
+            .map(Filter::parse)
+            .collect::<Result<Vec<Filter>, String>>()?;
+        Ok(Self {
+            key,
+            filters,
+            format,
+        })
+    }
+
+    pub fn apply(&self, color: RgbColor, palette: &Palette) -> Result<String, String> {
+        let mut color = color;
+        for filter in &self.filters {
+            color = filter.apply(color, palette)?;
+        }
+        Ok(color.format(self.format))
+    }
+}
+
+impl Filter {
+    pub fn parse(filter: &str) -> Result<Self, String> {
+        let filter = filter.trim();
+        let (name, args) = match filter.split_once('(') {
+            Some((name, args)) => {
+                let args = args
+                    .strip_suffix(')')
+                    .ok_or(format!("Missing ')' in filter '{filter}'"))?;
+                (name.trim(), split_top_level(args, ','))
+            }
+            None => (filter, vec![]),
+        };
+        let arg = |pos: usize| -> Result<f64, String> {
+            let arg = args
+                .get(pos)
+                .ok_or(format!("Filter '{name}' expects an argument"))?;
+            parse_amount(arg)
+        };
+        match name {
+            "lighten" => Ok(Filter::Lighten(arg(0)?)),
+            "darken" => Ok(Filter::Darken(arg(0)?)),
+            "saturate" => Ok(Filter::Saturate(arg(0)?)),
+            "desaturate" => Ok(Filter::Desaturate(arg(0)?)),
+            "hue" => Ok(Filter::Hue(
+                args.first()
+                    .ok_or(format!("Filter '{name}' expects an argument"))?
+                    .trim()
+                    .trim_end_matches("deg")
+                    .parse::<f64>()
+                    .map_err(|_| format!("Invalid angle in filter '{filter}'"))?,
+            )),
+            "mix" => Ok(Filter::Mix(
+                args.first()
+                    .ok_or(format!("Filter '{name}' expects a color name"))?
+                    .trim()
+                    .to_string(),
+                if args.len() > 1 { arg(1)? } else { 0.5 },
+            )),
+            "invert" => Ok(Filter::Invert),
+            "alpha" => Ok(Filter::Alpha(arg(0)?)),
+            _ => Err(format!("Unknown filter '{name}'")),
+        }
+    }
+
+    pub fn apply(&self, color: RgbColor, palette: &Palette) -> Result<RgbColor, String> {
+        Ok(match self {
+            Filter::Lighten(x) => color.lighten(*x),
+            Filter::Darken(x) => color.darken(*x),
+            Filter::Saturate(x) => color.saturate(*x),
+            Filter::Desaturate(x) => color.saturate(-x),
+            Filter::Hue(x) => color.rotate_hue(*x),
+            Filter::Mix(key, x) => {
+                let other = palette
+                    .lookup(key)?
+                    .ok_or(format!("Unknown color '{key}' in mix filter"))?;
+                color.mix(&other, *x)
+            }
+            Filter::Invert => color.invert(),
+            Filter::Alpha(x) => color.with_alpha(*x),
+        })
+    }
+}
+
+/// Parses `20%` as 0.2 and plain numbers as is
+fn parse_amount(arg: &str) -> Result<f64, String> {
+    let arg = arg.trim();
+    match arg.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map(|x| x / 100.0),
+        None => arg.parse::<f64>(),
+    }
+    .map_err(|_| format!("Invalid amount '{arg}'"))
+}
+
+/// Splits on `separator` except when it is inside parentheses
+fn split_top_level(data: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (pos, ch) in data.char_indices() {
+        match ch {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ch if ch == separator && depth == 0 => {
+                parts.push(&data[start..pos]);
+                start = pos + ch.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&data[start..]);
+    parts
+}
+
+pub struct TemplateOptions {
+    /// Path of the template, includes are relative to it
+    pub path: PathBuf,
+    pub delimiters: Delimiters,
+    /// Unknown placeholders are errors instead of being left as is
+    pub strict: bool,
+}
+
+pub struct TemplateReport {
+    pub problems: Vec<TemplateError>,
+    /// Names of colors used by template
+    pub used: Vec<String>,
+}
+
+/// Part of template between delimiters
+#[derive(Clone, Copy)]
+pub struct Tag<'a> {
+    pub content: &'a str,
+    /// Tag with delimiters as it is written in template
+    pub raw: &'a str,
+    pub offset: usize,
+}
+
+pub enum Token<'a> {
+    Text(&'a str),
+    Tag(Tag<'a>),
+}
+
+pub enum Node<'a> {
+    Text(&'a str),
+    Placeholder(Tag<'a>),
+    Include(String, Tag<'a>),
+    If {
+        condition: Condition,
+        then: Vec<Node<'a>>,
+        otherwise: Vec<Node<'a>>,
+    },
+    /// Repeats `body` for every color of the image, `var` points to the current one
+    For {
+        var: &'a str,
+        body: Vec<Node<'a>>,
+    },
+}
+
+pub enum Condition {
+    Dark,
+    Light,
+}
+
+enum BlockKind<'a> {
+    If(Condition),
+    For(&'a str),
+}
+
+struct Block<'a> {
+    kind: BlockKind<'a>,
+    tag: Tag<'a>,
+    then: Vec<Node<'a>>,
+    otherwise: Option<Vec<Node<'a>>>,
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "dark" => Ok(Condition::Dark),
+            "light" => Ok(Condition::Light),
+            _ => Err(format!(
+                "Unknown condition '{}', expected 'dark' or 'light'",
+                s.trim()
+            )),
+        }
+    }
+}
+
+impl Condition {
+    pub fn is_true(&self, palette: &Palette) -> bool {
+        match self {
+            Condition::Dark => palette.dark,
+            Condition::Light => !palette.dark,
+        }
+    }
+}
+
+impl Delimiters {
+    /// Empty delimiters would match everywhere
+    pub fn check(&self) -> Result<(), String> {
+        if self.open.is_empty() || self.close.is_empty() {
+            return Err(format!(
+                "delimiters can't be empty, got open '{}' and close '{}'",
+                self.open, self.close
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Delimiters {
+    fn default() -> Self {
+        Self {
+            open: OPEN.to_string(),
+            close: CLOSE.to_string(),
+        }
+    }
+}
+
+pub fn tokenize<'a>(data: &'a str, delimiters: &Delimiters) -> Vec<Token<'a>> {
+    let (open, close) = (delimiters.open.as_str(), delimiters.close.as_str());
+    if delimiters.check().is_err() {
+        return vec![Token::Text(data)];
+    }
+    let mut tokens = vec![];
+    let mut rest = 0;
+    while let Some(start) = data[rest..].find(open).map(|x| x + rest) {
+        let content_start = start + open.len();
+        if data[rest..start].ends_with(ESCAPE) {
+            tokens.push(Token::Text(&data[rest..start - ESCAPE.len_utf8()]));
+            tokens.push(Token::Text(&data[start..content_start]));
+            rest = content_start;
+            continue;
+        }
+        let Some(end) = data[content_start..].find(close).map(|x| x + content_start) else {
+            break;
+        };
+        if start > rest {
+            tokens.push(Token::Text(&data[rest..start]));
+        }
+        rest = end + close.len();
+        tokens.push(Token::Tag(Tag {
+            content: &data[content_start..end],
+            raw: &data[start..rest],
+            offset: start,
+        }));
+    }
+    if rest < data.len() {
+        tokens.push(Token::Text(&data[rest..]));
+    }
+    tokens
+}
+
+/// Block tags that are the only thing on their line are removed with that line
+fn trim_standalone<'a>(tokens: Vec<Token<'a>>) -> Vec<Token<'a>> {
+    let is_block = |token: Option<&Token>| match token {
+        Some(Token::Tag(tag)) => {
+            let keyword = tag.content.trim();
+            keyword.starts_with("if ")
+                || keyword.starts_with("for ")
+                || keyword.starts_with("include ")
+                || keyword == "else"
+                || keyword == "end"
+        }
+        _ => false,
+    };
+    // ranges of every text token that are kept
+    let mut ranges = tokens
+        .iter()
+        .map(|token| match token {
+            Token::Text(text) => (0, text.len()),
+            Token::Tag(_) => (0, 0),
+        })
+        .collect::<Vec<(usize, usize)>>();
+    for pos in 0..tokens.len() {
+        if !is_block(tokens.get(pos)) {
+            continue;
+        }
+        let before = match pos.checked_sub(1).map(|x| &tokens[x]) {
+            None => Some(0),
+            Some(Token::Text(text)) => match text.rfind('\n') {
+                Some(line) if text[line + 1..].trim().is_empty() => Some(line + 1),
+                None if pos == 1 && text.trim().is_empty() => Some(0),
+                _ => None,
+            },
+            Some(Token::Tag(_)) => None,
+        };
+        let after = match tokens.get(pos + 1) {
+            None => Some(0),
+            Some(Token::Text(text)) => {
+                let indent = text.len() - text.trim_start_matches([' ', '\t', '\r']).len();
+                text[indent..].starts_with('\n').then_some(indent + 1)
+            }
+            Some(Token::Tag(_)) => None,
+        };
+        if let (Some(before), Some(after)) = (before, after) {
+            if pos > 0 {
+                ranges[pos - 1].1 = ranges[pos - 1].1.min(before);
+            }
+            if pos + 1 < ranges.len() {
+                ranges[pos + 1].0 = ranges[pos + 1].0.max(after);
+            }
+        }
+    }
+    tokens
+        .into_iter()
+        .zip(ranges)
+        .map(|(token, (start, end))| match token {
+            Token::Text(text) => Token::Text(&text[start..end.max(start)]),
+            tag => tag,
+        })
+        .collect()
+}
+
+pub fn parse<'a>(data: &'a str, tokens: Vec<Token<'a>>) -> Result<Vec<Node<'a>>, TemplateError> {
+    let mut root = vec![];
+    let mut stack: Vec<Block> = vec![];
+    for token in tokens {
+        let node = match token {
+            Token::Text(text) => Node::Text(text),
+            Token::Tag(tag) => {
+                let keyword = tag.content.trim();
+                if let Some(condition) = keyword.strip_prefix("if ") {
+                    stack.push(Block {
+                        kind: BlockKind::If(
+                            Condition::from_str(condition)
+                                .map_err(|err| TemplateError::at(data, tag.offset, err))?,
+                        ),
+                        tag,
+                        then: vec![],
+                        otherwise: None,
+                    });
+                    continue;
+                }
+                if let Some(header) = keyword.strip_prefix("for ") {
+                    let var = match header.split_whitespace().collect::<Vec<&str>>()[..] {
+                        [var, "in", "palette"] => var,
+                        _ => {
+                            return Err(TemplateError::at(
+                                data,
+                                tag.offset,
+                                format!("Expected 'for <name> in palette', got {}", tag.raw),
+                            ))
+                        }
+                    };
+                    stack.push(Block {
+                        kind: BlockKind::For(var),
+                        tag,
+                        then: vec![],
+                        otherwise: None,
+                    });
+                    continue;
+                }
+                match keyword {
+                    "else" => {
+                        let block = stack
+                            .last_mut()
+                            .filter(|block| matches!(block.kind, BlockKind::If(_)))
+                            .ok_or(TemplateError::at(
+                                data,
+                                tag.offset,
+                                format!("{} without opening 'if'", tag.raw),
+                            ))?;
+                        if block.otherwise.is_some() {
+                            return Err(TemplateError::at(
+                                data,
+                                tag.offset,
+                                format!("Second {} in the same block", tag.raw),
+                            ));
+                        }
+                        block.otherwise = Some(vec![]);
+                        continue;
+                    }
+                    "end" => {
+                        let block = stack.pop().ok_or(TemplateError::at(
+                            data,
+                            tag.offset,
+                            format!("{} without opening block", tag.raw),
+                        ))?;
+                        match block.kind {
+                            BlockKind::If(condition) => Node::If {
+                                condition,
+                                then: block.then,
+                                otherwise: block.otherwise.unwrap_or_default(),
+                            },
+                            BlockKind::For(var) => Node::For {
+                                var,
+                                body: block.then,
+                            },
+                        }
+                    }
+                    _ => match keyword.strip_prefix("include ") {
+                        Some(path) => {
+                            let path = path
+                                .trim()
+                                .strip_prefix('"')
+                                .and_then(|x| x.strip_suffix('"'))
+                                .ok_or(TemplateError::at(
+                                    data,
+                                    tag.offset,
+                                    format!("Path in {} should be in quotes", tag.raw),
+                                ))?;
+                            Node::Include(path.to_string(), tag)
+                        }
+                        None => Node::Placeholder(tag),
+                    },
+                }
+            }
+        };
+        match stack.last_mut() {
+            Some(Block {
+                otherwise: Some(nodes),
+                ..
+            })
+            | Some(Block { then: nodes, .. }) => nodes.push(node),
+            None => root.push(node),
+        }
+    }
+    match stack.pop() {
+        Some(block) => Err(TemplateError::at(
+            data,
+            block.tag.offset,
+            format!("{} is never closed", block.tag.raw),
+        )),
+        None => Ok(root),
+    }
+}
+
+/// Reads included file relative to the current one, `stack` is the chain of
+/// templates that led to it
+fn read_include(path: &str, stack: &[PathBuf]) -> Result<(PathBuf, String), String> {
+    let dir = stack
+        .last()
+        .and_then(|x| x.parent())
+        .unwrap_or(Path::new(""));
+    let file = fs::canonicalize(dir.join(path))
+        .map_err(|err| format!("Failed to include '{path}': {err}"))?;
+    if let Some(pos) = stack.iter().position(|x| *x == file) {
+        let cycle = stack[pos..]
+            .iter()
+            .chain([&file])
+            .map(|x| x.display().to_string())
+            .collect::<Vec<String>>()
+            .join(" -> ");
+        return Err(format!("Include cycle: {cycle}"));
+    }
+    let data = fs::read_to_string(&file)
+        .map_err(|err| format!("Failed to include '{path}': {err}"))?;
+    Ok((file, data))
+}
+
+/// Splits key that starts with a loop variable into the color position and the rest of the key
+fn loop_key<'a>(key: &'a str, vars: &[(&str, usize)]) -> Option<(usize, &'a str)> {
+    let (base, rest) = match key.find('.') {
+        Some(pos) => key.split_at(pos),
+        None => (key, ""),
+    };
+    vars.iter()
+        .rev()
+        .find(|(var, _)| *var == base)
+        .map(|(_, pos)| (*pos, rest))
+}
+
+/// `c.index` and `c.share` of a loop variable, `None` for everything else
+fn loop_value(rest: &str, pos: usize, palette: &Palette) -> Option<String> {
+    match rest {
+        ".index" => Some(pos.to_string()),
+        ".share" => Some(format!("{:.3}", palette.shares.get(pos).unwrap_or(&0.0))),
+        _ => None,
+    }
+}
+
+fn render_nodes(
+    data: &str,
+    nodes: &[Node],
+    palette: &Palette,
+    options: &TemplateOptions,
+    stack: &[PathBuf],
+    vars: &[(&str, usize)],
+    out: &mut String,
+) -> Result<(), TemplateError> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Placeholder(tag) => {
+                let key = Placeholder::key_of(tag.content);
+                let key = match loop_key(key, vars) {
+                    Some((pos, rest)) => match loop_value(rest, pos, palette) {
+                        Some(value) => {
+                            out.push_str(&value);
+                            continue;
+                        }
+                        None => format!("color{pos}{rest}"),
+                    },
+                    None => key.to_string(),
+                };
+                let color = palette
+                    .lookup(&key)
+                    .map_err(|err| TemplateError::at(data, tag.offset, err))?;
+                match color {
+                    Some(color) => {
+                        let value = Placeholder::parse(tag.content)
+                            .and_then(|placeholder| placeholder.apply(color, palette))
+                            .map_err(|err| TemplateError::at(data, tag.offset, err))?;
+                        out.push_str(&value);
+                    }
+                    None if options.strict => {
+                        return Err(TemplateError::at(
+                            data,
+                            tag.offset,
+                            format!("Unknown placeholder {}", tag.raw),
+                        ))
+                    }
+                    None => out.push_str(tag.raw),
+                }
+            }
+            Node::Include(path, tag) => {
+                let (file, included) = read_include(path, stack)
+                    .map_err(|err| TemplateError::at(data, tag.offset, err))?;
+                let stack = [stack, std::slice::from_ref(&file)].concat();
+                // loop variables of the including template can be used by the included one
+                let rendered =
+                    render_template(&included, palette, options, &stack, vars).map_err(|err| {
+                        TemplateError::at(data, tag.offset, format!("{}:{err}", file.display()))
+                    })?;
+                out.push_str(&rendered);
+            }
+            Node::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let nodes = if condition.is_true(palette) {
+                    then
+                } else {
+                    otherwise
+                };
+                render_nodes(data, nodes, palette, options, stack, vars, out)?;
+            }
+            Node::For { var, body } => {
+                for pos in 0..palette.colors.len() {
+                    let vars = [vars, &[(*var, pos)]].concat();
+                    render_nodes(data, body, palette, options, stack, &vars, out)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Placeholders and includes of template, including the ones in blocks that won't be rendered,
+/// together with loop variables they can use
+fn leaves<'a>(nodes: &'a [Node<'a>], vars: &[&'a str]) -> Vec<(Vec<&'a str>, &'a Node<'a>)> {
+    nodes
+        .iter()
+        .flat_map(|node| match node {
+            Node::Text(_) => vec![],
+            Node::Placeholder(_) | Node::Include(..) => vec![(vars.to_vec(), node)],
+            Node::If {
+                then, otherwise, ..
+            } => {
+                let mut nodes = leaves(then, vars);
+                nodes.extend(leaves(otherwise, vars));
+                nodes
+            }
+            Node::For { var, body } => leaves(body, &[vars, &[*var]].concat()),
+        })
+        .collect()
+}
+
+fn check_template(
+    data: &str,
+    palette: &Palette,
+    options: &TemplateOptions,
+    stack: &[PathBuf],
+    vars: &[&str],
+) -> TemplateReport {
+    let mut report = TemplateReport {
+        problems: vec![],
+        used: vec![],
+    };
+    let nodes = match parse(data, trim_standalone(tokenize(data, &options.delimiters))) {
+        Ok(nodes) => nodes,
+        Err(err) => {
+            report.problems.push(err);
+            return report;
+        }
+    };
+    for (vars, node) in leaves(&nodes, vars) {
+        match node {
+            Node::Placeholder(tag) => {
+                // loop variables are checked against the first color
+                let vars = vars.iter().map(|var| (*var, 0)).collect::<Vec<(&str, usize)>>();
+                let key = Placeholder::key_of(tag.content);
+                let key = match loop_key(key, &vars) {
+                    Some((pos, rest)) if loop_value(rest, pos, palette).is_some() => continue,
+                    Some(_) if palette.colors.is_empty() => continue,
+                    Some((pos, rest)) => format!("color{pos}{rest}"),
+                    None => key.to_string(),
+                };
+                let problem = match palette.lookup(&key) {
+                    Ok(Some(_)) => Placeholder::parse(tag.content).map(|placeholder| {
+                        for filter in placeholder.filters {
+                            if let Filter::Mix(key, _) = filter {
+                                report.used.push(key);
+                            }
+                        }
+                    }),
+                    Ok(None) => Err(format!("Unknown placeholder {}", tag.raw)),
+                    Err(err) => Err(err),
+                };
+                report
+                    .used
+                    .push(key.split('.').next().unwrap_or_default().to_string());
+                if let Err(err) = problem {
+                    report.problems.push(TemplateError::at(data, tag.offset, err));
+                }
+            }
+            Node::Include(path, tag) => match read_include(path, stack) {
+                Ok((file, included)) => {
+                    let stack = [stack, std::slice::from_ref(&file)].concat();
+                    let nested = check_template(&included, palette, options, &stack, &vars);
+                    report.used.extend(nested.used);
+                    report.problems.extend(nested.problems.iter().map(|err| {
+                        TemplateError::at(data, tag.offset, format!("{}:{err}", file.display()))
+                    }));
+                }
+                Err(err) => report.problems.push(TemplateError::at(data, tag.offset, err)),
+            },
+            _ => {}
+        }
+    }
+    report
+}
+
+fn render_template(
+    data: &str,
+    palette: &Palette,
+    options: &TemplateOptions,
+    stack: &[PathBuf],
+    vars: &[(&str, usize)],
+) -> Result<String, TemplateError> {
+    let nodes = parse(data, trim_standalone(tokenize(data, &options.delimiters)))?;
+    let mut out = String::with_capacity(data.len());
+    render_nodes(data, &nodes, palette, options, stack, vars, &mut out)?;
+    Ok(out)
+}
+
+fn root_stack(options: &TemplateOptions) -> Vec<PathBuf> {
+    vec![fs::canonicalize(&options.path).unwrap_or(options.path.clone())]
+}
+
+/// Finds problems without rendering the template
+pub fn check(data: &str, palette: &Palette, options: &TemplateOptions) -> TemplateReport {
+    check_template(data, palette, options, &root_stack(options), &[])
+}
+
+pub fn render(
+    data: &str,
+    palette: &Palette,
+    options: &TemplateOptions,
+) -> Result<String, TemplateError> {
+    render_template(data, palette, options, &root_stack(options), &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::config::tests::{test_dir, test_palette};
+
+    fn options(open: &str, close: &str) -> TemplateOptions {
+        TemplateOptions {
+            path: PathBuf::from("test.tpl"),
+            delimiters: Delimiters {
+                open: open.to_string(),
+                close: close.to_string(),
+            },
+            strict: false,
+        }
+    }
+
+    fn run(data: &str) -> Result<String, TemplateError> {
+        render(data, &test_palette(true), &options(OPEN, CLOSE))
+    }
+
+    #[test]
+    fn unknown_placeholders_are_kept_or_fail_in_strict_mode() {
+        assert_eq!(
+            run("bg=$[primary] fg=$[color1]").unwrap(),
+            "bg=#112233 fg=#445566"
+        );
+        assert_eq!(run("x=$[unknown]").unwrap(), "x=$[unknown]");
+        let strict = TemplateOptions {
+            strict: true,
+            ..options(OPEN, CLOSE)
+        };
+        let err = render("x\n  $[unknown]", &test_palette(true), &strict).unwrap_err();
+        assert_eq!((err.line, err.column), (2, 3));
+        assert_eq!(err.message, "Unknown placeholder $[unknown]");
+    }
+
+    #[test]
+    fn if_blocks_follow_mode() {
+        let data = "$[if dark]d$[else]l$[end] $[if light]only light$[end]";
+        assert_eq!(run(data).unwrap(), "d ");
+        let light = render(data, &test_palette(false), &options(OPEN, CLOSE)).unwrap();
+        assert_eq!(light, "l only light");
+        let data = "$[if dark]$[if light]x$[else]y$[end]$[end]";
+        assert_eq!(run(data).unwrap(), "y");
+    }
+
+    #[test]
+    fn standalone_block_lines_are_removed() {
+        let data = "a\n  $[if dark]\n  dark\n  $[else]\n  light\n  $[end]\nb\n";
+        assert_eq!(run(data).unwrap(), "a\n  dark\nb\n");
+        let data = "$[if dark]\r\nx\r\n$[end]\r\ny";
+        assert_eq!(run(data).unwrap(), "x\r\ny");
+        // tags that share the line with text keep it
+        assert_eq!(run("a $[if dark]b$[end]\nc").unwrap(), "a b\nc");
+        assert_eq!(run("$[primary]\n").unwrap(), "#112233\n");
+    }
+
+    #[test]
+    fn mismatched_blocks_report_position() {
+        let err = run("a\nb $[end]").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 3));
+        assert_eq!(err.message, "$[end] without opening block");
+
+        let err = run("x\n\n  $[if dark]\nnever closed").unwrap_err();
+        assert_eq!((err.line, err.column), (3, 3));
+        assert_eq!(err.message, "$[if dark] is never closed");
+
+        let err = run("$[else]").unwrap_err();
+        assert_eq!(err.message, "$[else] without opening 'if'");
+
+        let err = run("$[if dark]a$[else]b$[else]c$[end]").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 20));
+
+        let err = run("é$[if dim]$[end]").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 2));
+        assert!(err.message.contains("Unknown condition 'dim'"), "{err}");
+    }
+
+    #[test]
+    fn escaped_delimiters_are_kept() {
+        assert_eq!(run(r"a \$[primary] b").unwrap(), "a $[primary] b");
+        assert_eq!(run(r"\$[primary]$[primary]").unwrap(), "$[primary]#112233");
+        assert_eq!(run(r"\$[if dark]").unwrap(), "$[if dark]");
+    }
+
+    #[test]
+    fn custom_delimiters() {
+        let braces = options("{{", "}}");
+        assert_eq!(
+            render(
+                "a {{primary}} $[primary] \\{{primary}}",
+                &test_palette(true),
+                &braces
+            )
+            .unwrap(),
+            "a #112233 $[primary] {{primary}}"
+        );
+        assert_eq!(
+            render(
+                "{{if light}}l{{else}}d{{end}}",
+                &test_palette(true),
+                &braces
+            )
+            .unwrap(),
+            "d"
+        );
+        assert_eq!(
+            render(
+                "x = @@primary:rgb@@",
+                &test_palette(true),
+                &options("@@", "@@")
+            )
+            .unwrap(),
+            "x = rgb(17, 34, 51)"
+        );
+    }
+
+    #[test]
+    fn empty_delimiters_are_rejected() {
+        assert!(Delimiters::default().check().is_ok());
+        assert!(options("", "").delimiters.check().is_err());
+        assert!(options("$[", "").delimiters.check().is_err());
+        // text is kept as is instead of looping over empty matches
+        assert_eq!(
+            render("abc", &test_palette(true), &options("", "")).unwrap(),
+            "abc"
+        );
+    }
+
+    #[test]
+    fn loops_over_palette() {
+        let data = "$[for c in palette]\n$[if dark]\n$[c.index]=$[c] $[c.share]\n$[end]\n$[end]\n";
+        assert_eq!(
+            run(data).unwrap(),
+            "0=#112233 0.500\n1=#445566 0.300\n2=#778899 0.200\n"
+        );
+        let data = "$[for a in palette]$[for b in palette]$[a.index]$[b.index] $[end]$[end]";
+        assert_eq!(run(data).unwrap(), "00 01 02 10 11 12 20 21 22 ");
+        let data = "$[if light]$[for c in palette]$[c:rgb];$[end]$[else]none$[end]";
+        assert_eq!(run(data).unwrap(), "none");
+        assert_eq!(
+            render(data, &test_palette(false), &options(OPEN, CLOSE)).unwrap(),
+            "rgb(17, 34, 51);rgb(68, 85, 102);rgb(119, 136, 153);"
+        );
+    }
+
+    #[test]
+    fn loop_errors_report_position() {
+        let err = run("x\n$[for c in colors]$[end]").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 1));
+        assert_eq!(
+            err.message,
+            "Expected 'for <name> in palette', got $[for c in colors]"
+        );
+        let err = run("$[for c in palette]\n$[else]\n$[end]").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 1));
+        assert_eq!(err.message, "$[else] without opening 'if'");
+        let err = run("$[for c in palette]$[c]").unwrap_err();
+        assert_eq!(err.message, "$[for c in palette] is never closed");
+    }
+
+    fn render_file(path: &Path) -> Result<String, TemplateError> {
+        let options = TemplateOptions {
+            path: path.to_path_buf(),
+            ..options(OPEN, CLOSE)
+        };
+        render(
+            &fs::read_to_string(path).unwrap(),
+            &test_palette(true),
+            &options,
+        )
+    }
+
+    #[test]
+    fn includes_nest_relative_to_including_file() {
+        let dir = test_dir("include");
+        fs::create_dir_all(dir.join("parts")).unwrap();
+        fs::create_dir_all(dir.join("shared")).unwrap();
+        fs::write(
+            dir.join("main.tpl"),
+            "a\n$[include \"parts/colors.tpl\"]\nz\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("parts/colors.tpl"),
+            "bg=$[primary]\n$[include \"../shared/fg.tpl\"]\n",
+        )
+        .unwrap();
+        fs::write(dir.join("shared/fg.tpl"), "fg=$[secondary]\n").unwrap();
+        assert_eq!(
+            render_file(&dir.join("main.tpl")).unwrap(),
+            "a\nbg=#112233\nfg=#445566\nz\n"
+        );
+
+        fs::write(dir.join("missing.tpl"), "x\n  $[include \"nope.tpl\"]").unwrap();
+        let err = render_file(&dir.join("missing.tpl")).unwrap_err();
+        assert_eq!((err.line, err.column), (2, 3));
+        assert!(
+            err.message.starts_with("Failed to include 'nope.tpl'"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn include_cycles_are_reported() {
+        let dir = fs::canonicalize(test_dir("include-cycle")).unwrap();
+        fs::write(dir.join("x.tpl"), "$[include \"y.tpl\"]").unwrap();
+        fs::write(dir.join("y.tpl"), "y\n$[include \"x.tpl\"]").unwrap();
+        let (x, y) = (dir.join("x.tpl"), dir.join("y.tpl"));
+        let cycle = format!(
+            "Include cycle: {} -> {} -> {}",
+            x.display(),
+            y.display(),
+            x.display()
+        );
+        let err = render_file(&x).unwrap_err();
+        assert!(err.message.ends_with(&cycle), "{err}");
+
+        fs::write(dir.join("self.tpl"), "$[include \"self.tpl\"]").unwrap();
+        let err = render_file(&dir.join("self.tpl")).unwrap_err();
+        assert!(err.message.contains("Include cycle"), "{err}");
+
+        let options = TemplateOptions {
+            path: x.clone(),
+            ..options(OPEN, CLOSE)
+        };
+        let report = check(
+            &fs::read_to_string(&x).unwrap(),
+            &test_palette(true),
+            &options,
+        );
+        assert_eq!(report.problems.len(), 1);
+        assert!(
+            report.problems[0].message.ends_with(&cycle),
+            "{}",
+            report.problems[0]
+        );
+    }
+
+    #[test]
+    fn included_files_see_loop_variables() {
+        let dir = test_dir("include-loop");
+        fs::write(
+            dir.join("main.tpl"),
+            "$[for c in palette]\n$[include \"item.tpl\"]\n$[end]\n",
+        )
+        .unwrap();
+        fs::write(dir.join("item.tpl"), "$[c.index]=$[c] on $[c.on]\n").unwrap();
+        assert_eq!(
+            render_file(&dir.join("main.tpl")).unwrap(),
+            "0=#112233 on #FFFFFF\n1=#445566 on #FFFFFF\n2=#778899 on #000000\n"
+        );
+        let options = TemplateOptions {
+            path: dir.join("main.tpl"),
+            strict: true,
+            ..options(OPEN, CLOSE)
+        };
+        let data = fs::read_to_string(dir.join("main.tpl")).unwrap();
+        assert!(check(&data, &test_palette(true), &options)
+            .problems
+            .is_empty());
+    }
+
+    #[test]
+    fn check_reports_problems_and_used_colors() {
+        let data = "$[primary|mix(secondary)]\n$[if light]\n$[nope]\n$[end]\n$[primary:rgb\n";
+        let report = check(data, &test_palette(true), &options(OPEN, CLOSE));
+        assert_eq!(report.used, vec!["secondary", "primary", "nope"]);
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!((report.problems[0].line, report.problems[0].column), (3, 1));
+    }
+}
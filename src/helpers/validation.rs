@@ -0,0 +1,115 @@
+use std::{
+    ffi::CString,
+    fmt::Display,
+    fs,
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+};
+
+/// Problem in config that makes apply fail or write something unexpected
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidationError {
+    /// Variable that is not defined or a broken glob
+    Path(String),
+    /// Directory or glob without any files
+    NoFilesFound(PathBuf),
+    MissingTemplate(PathBuf),
+    Unwritable(PathBuf, String),
+    DuplicateTarget(PathBuf),
+    /// `to` of one entry is `from` of another
+    TargetIsSource(PathBuf),
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::Path(err) => write!(f, "{err}"),
+            ValidationError::NoFilesFound(path) => write!(f, "{}: no files found", path.display()),
+            ValidationError::MissingTemplate(path) => {
+                write!(f, "{}: template doesn't exist", path.display())
+            }
+            ValidationError::Unwritable(path, reason) => {
+                write!(f, "{}: can't be written, {reason}", path.display())
+            }
+            ValidationError::DuplicateTarget(path) => {
+                write!(f, "{}: written by more than one entry", path.display())
+            }
+            ValidationError::TargetIsSource(path) => write!(
+                f,
+                "{}: written by one entry and used as template by another",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl ValidationError {
+    /// Whether the problem belongs to the entry reading `from` and writing `to`
+    pub fn concerns(&self, from: Option<&Path>, to: &Path) -> bool {
+        match self {
+            ValidationError::Path(_) | ValidationError::NoFilesFound(_) => false,
+            ValidationError::MissingTemplate(path) => from == Some(path.as_path()),
+            ValidationError::Unwritable(path, _)
+            | ValidationError::DuplicateTarget(path)
+            | ValidationError::TargetIsSource(path) => normalize(path) == normalize(to),
+        }
+    }
+}
+
+/// Same path for `a/../b` and `b`, parts that don't exist yet are kept as they are
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for part in path.components() {
+        match part {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            _ => out.push(part),
+        }
+    }
+    let Some(existing) = out.ancestors().find(|x| x.exists()) else {
+        return out;
+    };
+    match (fs::canonicalize(existing), out.strip_prefix(existing)) {
+        (Ok(base), Ok(rest)) => base.join(rest),
+        _ => out,
+    }
+}
+
+/// Checks that the file can be replaced. Files are written to a temporary sibling
+/// and renamed, so the closest existing directory has to accept new files
+pub fn writable(path: &Path) -> Result<(), String> {
+    if path.is_dir() {
+        return Err("it is a directory".to_string());
+    }
+    // symlinks are followed, the file they point to is the one replaced
+    let path = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    if path.exists() {
+        access(&path)?;
+    }
+    let Some(dir) = path
+        .ancestors()
+        .skip(1)
+        .map(|x| if x.as_os_str().is_empty() { Path::new(".") } else { x })
+        .find(|x| x.exists())
+    else {
+        return Ok(());
+    };
+    if !dir.is_dir() {
+        return Err(format!("{} is not a directory", dir.display()));
+    }
+    access(dir).map_err(|err| format!("{}: {err}", dir.display()))
+}
+
+/// Asks the system whether the current user can write `path`, permission bits alone
+/// don't know about groups, ACLs or read-only mounts
+fn access(path: &Path) -> Result<(), String> {
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(|err| err.to_string())?;
+    // SAFETY: `path` is a valid nul terminated string that outlives the call
+    if unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error().to_string())
+    }
+}
@@ -4,12 +4,15 @@ pub mod helpers;
 pub mod pages;
 mod tabs;
 pub mod traits;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use app::App;
-use clap::{command, Parser};
+use clap::{command, Parser, Subcommand};
 use color_eyre::Result;
-use helpers::config::{Config, ReplaceColors};
+use helpers::{
+    backup::Backup,
+    config::{expand_path, Config, Mode},
+};
 use pages::image_input::ImageInputTui;
 use ratatui::style::Color;
 
@@ -29,6 +32,25 @@ struct Args {
     /// Silence all output in cli mode
     #[arg(short, long, default_missing_value = "true", default_value = "false")]
     silence: bool,
+    /// Overrides mode from config, used by $[if dark] and $[if light] blocks
+    #[arg(short, long)]
+    mode: Option<Mode>,
+    /// Print changes as unified diffs without writing anything in cli mode
+    #[arg(short, long, default_missing_value = "true", default_value = "false")]
+    dry_run: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Lists backups, or puts files back as they were before the chosen apply
+    Restore {
+        /// Name of the backup or its position in the list, 1 is the newest
+        backup: Option<String>,
+    },
+    /// Checks config for missing templates and destinations that can't be written
+    Validate,
 }
 
 pub struct Logger {
@@ -40,40 +62,166 @@ impl Logger {
             println!("{}", data)
         }
     }
+    /// Errors are printed even in silent mode
+    pub fn error(&self, data: &str) {
+        eprintln!("{}", data)
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    let cfg = if let Some(path) = args.path_cfg {
+    let mut cfg = if let Some(path) = args.path_cfg {
         Config::from_path(path)
     } else {
         Config::new()
-    };
+    }
+    .unwrap_or_else(|err| {
+        eprintln!("{err}");
+        std::process::exit(1);
+    });
+    if let Some(mode) = args.mode {
+        cfg.set_mode(mode);
+    }
+    let image = args
+        .image
+        .map(|x| expand_path(&x, Path::new("")))
+        .transpose()
+        .unwrap_or_else(|err| {
+            eprintln!("{err}");
+            std::process::exit(1);
+        });
+    if let Some(Command::Restore { backup }) = args.command {
+        let logger = Logger {
+            silent: args.silence,
+        };
+        let dir = Config::get_backup_dir();
+        let Some(id) = backup else {
+            let backups = Backup::list(&dir);
+            if backups.is_empty() {
+                logger.log("No backups yet");
+            }
+            for (pos, backup) in backups.iter().enumerate() {
+                logger.log(&format!("{}. {}", pos + 1, backup.summary()));
+            }
+            return Ok(());
+        };
+        let backup = Backup::find(&dir, &id).unwrap_or_else(|err| {
+            logger.error(&err);
+            std::process::exit(1);
+        });
+        let errors = cfg.restore(&backup);
+        for error in &errors {
+            logger.error(error);
+        }
+        if !errors.is_empty() {
+            std::process::exit(1);
+        }
+        logger.log(&format!("Restored files from {}", backup.created));
+        return Ok(());
+    }
+    if let Some(Command::Validate) = args.command {
+        let logger = Logger {
+            silent: args.silence,
+        };
+        let problems = cfg.validate();
+        for problem in &problems {
+            logger.error(&problem.to_string());
+        }
+        if !problems.is_empty() {
+            logger.error(&format!("Found {} problems in config", problems.len()));
+            std::process::exit(1);
+        }
+        logger.log("Config is valid");
+        return Ok(());
+    }
     if args.cli {
         let logger = Logger {
             silent: args.silence,
         };
         logger.log("Getting colors from image...");
-        let image = args
-            .image
+        let image = image
             .expect("--image parameter shoudn't be empty!")
             .into_os_string()
             .into_string()
             .expect("Failed to use image path");
         let colors = image_palette::load(&image).expect("Failed to extract colors from image");
+        let total = colors.0.iter().map(|x| x.count() as u64).sum::<u64>().max(1);
+        let shares = colors.0.iter().map(|x| x.count() as f64 / total as f64).collect();
         let colors = colors.0.iter().map(|x| {
             let color = x.color();
             Color::Rgb(color.0, color.1, color.2).to_string()
         }).collect::<Vec<String>>();
-        let colors = ReplaceColors {
-            primary: colors[0].clone(),
-            secondary: colors[1].clone(),
-            tertiary: colors[2].clone(),
-        };
-        logger.log(&format!("Got colors from image {:?}", colors));
+        let palette = cfg.default_palette(colors, shares).unwrap_or_else(|err| {
+            logger.error(&err);
+            std::process::exit(1);
+        });
+        logger.log(&format!("Got colors from image {:?}", palette.roles));
+        for problem in cfg.check(&palette) {
+            logger.log(&format!("Warning: {problem}"));
+        }
+        if args.dry_run {
+            let mut errors = vec![];
+            for diff in cfg.diff(&palette) {
+                match diff {
+                    Ok(diff) => print!("{diff}"),
+                    Err(err) => errors.push(err),
+                }
+            }
+            for error in &errors {
+                logger.error(error);
+            }
+            if !errors.is_empty() {
+                logger.error(&format!("Failed to render {} files", errors.len()));
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
         logger.log("Replacing files...");
-        cfg.process(&colors);
+        let report = cfg.process(&palette);
+        for error in &report.errors {
+            logger.error(error);
+        }
+        if !report.files.is_empty() {
+            logger.log(&format!("{:<10} File", "Status"));
+        }
+        for file in &report.files {
+            let line = format!("{:<10} {}", file.status.name(), file.to.display());
+            match file.status.reason() {
+                Some(reason) => logger.error(&format!("{line}  ({reason})")),
+                None => logger.log(&line),
+            }
+        }
+        for hook in &report.hooks {
+            for (pos, line) in hook.lines().iter().enumerate() {
+                let line = if pos == 0 { format!("Hook {line}") } else { line.clone() };
+                if hook.success() {
+                    logger.log(&line);
+                } else {
+                    logger.error(&line);
+                }
+            }
+        }
+        if !report.files.is_empty() {
+            logger.log(&report.summary());
+        }
+        if report.aborted {
+            std::process::exit(1);
+        }
+        if report.failed() > 0 {
+            logger.error(&format!(
+                "Failed to process {} of {} files",
+                report.failed(),
+                report.files.len()
+            ));
+            std::process::exit(1);
+        }
+        if !report.errors.is_empty() {
+            std::process::exit(1);
+        }
+        if report.hooks.iter().any(|x| !x.success()) {
+            std::process::exit(1);
+        }
         logger.log("Completed!");
         Ok(())
     } else {
@@ -81,7 +229,7 @@ async fn main() -> Result<()> {
         let mut terminal = ratatui::init();
         terminal.clear().unwrap();
         let mut app = App::new(cfg);
-        if let Some(path) = args.image {
+        if let Some(path) = image {
             app.tx
                 .send(
                     ImageInputTui::UsePath(path.into_os_string().into_string().unwrap()).into(),
@@ -1,4 +1,4 @@
-use std::{num::Saturating, time::Duration};
+use std::{num::Saturating, str::FromStr, time::Duration};
 
 use crate::{
     app::{Pages, Tui},
@@ -9,7 +9,12 @@ use crate::{
         image::{CustomImage, ImageState, ImageStruct},
         input_bar::Input,
     },
-    helpers::config::{Config, ReplaceColors},
+    helpers::{
+        backup::Backup,
+        color::{Harmony, RgbColor},
+        config::{ApplyReport, Config, FileResult, FileStatus, Mode, Palette, ReplaceColors, ReplaceFile},
+        hooks::HookResult,
+    },
     traits::{
         focus_tracker::FocusTracker,
         get_input::{get_axis, DefaultInputComponent, InputComponent},
@@ -18,7 +23,7 @@ use crate::{
 use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
 use futures::{FutureExt, StreamExt};
 use ratatui::{
-    layout::{Alignment, Constraint, Direction, Layout, Margin}, style::Stylize, symbols, text::{Line, Text}, widgets::{Block, BorderType, Paragraph, Widget}, DefaultTerminal, Frame
+    layout::{Alignment, Constraint, Direction, Layout, Margin}, style::{Color, Stylize}, symbols, text::{Line, Span, Text}, widgets::{Block, BorderType, Paragraph, Widget}, DefaultTerminal, Frame
 };
 use tokio::{
     sync::mpsc::{self, Receiver, Sender},
@@ -30,21 +35,45 @@ pub struct ApplyPage {
     pub tx: Sender<Tui>,
     pub focused: FocusTracker,
     pub completed: bool,
+    /// Set while files are written and hooks run in the background
+    pub applying: bool,
     pub image: Option<ImageData>,
     pub selected_colors: ReplaceColors<ColorPicker>,
     pub color_component: ColorComponent,
     pub cfg: Config,
+    /// Files found by the config, updated on entering the page and after every apply
+    pub files: Vec<ReplaceFile>,
+    pub errors: Vec<String>,
+    /// What happened to every file during the last apply
+    pub results: Vec<FileResult>,
+    /// Count of every status in `results`
+    pub summary: String,
+    /// Hooks of the last apply
+    pub hooks: Vec<HookResult>,
+    /// Found in templates before anything is written
+    pub problems: Vec<String>,
+    pub mode: Mode,
+    /// Lines of the changes preview, shown instead of the file list
+    pub diff: Option<Vec<String>>,
+    pub diff_scroll: u16,
+    /// Backups to choose from when restoring, shown instead of the file list
+    pub backups: Option<Vec<Backup>>,
+    pub backup_pos: usize,
 }
 
 #[derive(Clone)]
 pub struct ImageData {
     pub image_path: String,
     pub colors: Vec<String>,
+    /// Share of the image for every color
+    pub shares: Vec<f64>,
 }
 
 pub enum ApplyTui {
     Event(Event),
     ContinueButton(),
+    /// Apply finished in the background
+    Applied(ApplyReport),
     Delayed,
 }
 
@@ -75,33 +104,103 @@ impl ApplyPage {
                 btn
             },
             tx,
-            focused: FocusTracker::new(vec![1, 1, 1, 1]),
+            focused: FocusTracker::new(vec![1; cfg.get_roles().len() + 1]),
+            mode: cfg.get_mode(),
             completed: false,
+            applying: false,
             image: None,
             color_component: ColorComponent::new(),
             cfg,
-            selected_colors: ReplaceColors {
-                primary: ColorPicker::new(Vec::new()),
-                secondary: ColorPicker::new(Vec::new()),
-                tertiary: ColorPicker::new(Vec::new()),
-            },
+            selected_colors: ReplaceColors::new(Vec::new()),
+            files: Vec::new(),
+            errors: Vec::new(),
+            results: Vec::new(),
+            summary: String::new(),
+            hooks: Vec::new(),
+            problems: Vec::new(),
+            diff: None,
+            diff_scroll: 0,
+            backups: None,
+            backup_pos: 0,
         }
     }
 
+    fn handle_backups_key(&mut self, code: KeyCode) {
+        let Some(backups) = &self.backups else {
+            return;
+        };
+        match code {
+            KeyCode::Up => self.backup_pos = self.backup_pos.saturating_sub(1),
+            KeyCode::Down => {
+                self.backup_pos = (self.backup_pos + 1).min(backups.len().saturating_sub(1))
+            }
+            KeyCode::Esc => self.backups = None,
+            KeyCode::Enter => {
+                if let Some(backup) = backups.get(self.backup_pos) {
+                    self.errors = self.cfg.restore(backup);
+                    if self.errors.is_empty() {
+                        self.continue_button.change_title("Restored!");
+                    } else {
+                        self.continue_button
+                            .change_title(format!("Failed: {}", self.errors.len()));
+                    }
+                    let tx = self.tx.clone();
+                    tokio::task::spawn(async move {
+                        sleep(Duration::from_secs(2)).await;
+                        tx.send(ApplyTui::Delayed.into()).await.unwrap();
+                    });
+                }
+                self.backups = None;
+                if self.diff.is_some() {
+                    self.update_diff();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Renders every file into memory and keeps the diff against current destinations
+    pub fn update_diff(&mut self) {
+        let lines = self
+            .cfg
+            .diff(&self.get_palette())
+            .into_iter()
+            .flat_map(|diff| match diff {
+                Ok(diff) => diff.lines().map(String::from).collect::<Vec<String>>(),
+                Err(err) => vec![format!("! {err}")],
+            })
+            .collect::<Vec<String>>();
+        self.diff = Some(if lines.is_empty() {
+            vec!["No changes".to_string()]
+        } else {
+            lines
+        });
+    }
+
     pub fn set_data(&mut self, data: ImageData) {
+        self.files = self.cfg.get_files();
         self.color_component.set_colors(data.colors.clone());
-        self.selected_colors = ReplaceColors {
-            primary: ColorPicker::new(data.colors.clone())
-                .set_title("Primary  ".into())
-                .with_pos(0),
-            secondary: ColorPicker::new(data.colors.clone())
-                .set_title("Secondary".into())
-                .with_pos(1),
-            tertiary: ColorPicker::new(data.colors.clone())
-                .set_title("Tertiary ".into())
-                .with_pos(2),
-        };
+        let roles = self.cfg.get_roles();
+        let width = roles.iter().map(|x| x.name.len()).max().unwrap_or(0);
+        self.selected_colors = ReplaceColors::new(
+            roles
+                .iter()
+                .map(|role| {
+                    let mut title = format!("{:<width$}", role.name);
+                    if let Some(first) = title.get_mut(0..1) {
+                        first.make_ascii_uppercase();
+                    }
+                    let picker = ColorPicker::new(data.colors.clone())
+                        .set_title(title)
+                        .with_pos(role.index);
+                    (role.name.clone(), picker)
+                })
+                .collect(),
+        );
+        self.focused = FocusTracker::new(vec![1; roles.len() + 1]);
+        self.continue_button.set_focused(true);
         self.image = Some(data);
+        self.problems = self.cfg.check(&self.get_palette());
     }
 
     pub fn get_component(&mut self, pos: (u64, u64)) -> &mut dyn DefaultInputComponent {
@@ -109,23 +208,73 @@ impl ApplyPage {
             0 => match pos.1 {
                 _ => &mut self.continue_button as &mut dyn DefaultInputComponent,
             },
-            1 => match pos.1 {
-                _ => &mut self.selected_colors.primary as &mut dyn DefaultInputComponent,
-            },
-            2 => match pos.1 {
-                _ => &mut self.selected_colors.secondary as &mut dyn DefaultInputComponent,
-            },
-            3 => match pos.1 {
-                _ => &mut self.selected_colors.tertiary as &mut dyn DefaultInputComponent,
+            row => match self.selected_colors.roles.get_mut(row as usize - 1) {
+                Some((_, picker)) => picker as &mut dyn DefaultInputComponent,
+                None => &mut self.continue_button as &mut dyn DefaultInputComponent,
             },
-            _ => &mut self.continue_button as &mut dyn DefaultInputComponent,
         }
     }
 
+    pub fn get_palette(&self) -> Palette {
+        self.cfg.palette(
+            self.selected_colors.map(|picker| picker.get_color()),
+            self.image.as_ref().map(|x| x.colors.clone()).unwrap_or_default(),
+            self.image.as_ref().map(|x| x.shares.clone()).unwrap_or_default(),
+            self.mode,
+        )
+    }
+
     pub fn handle_event(&mut self, event: ApplyTui) {
         match event {
             ApplyTui::Event(event) => match event {
                 Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
+                    if key_event.modifiers == KeyModifiers::CONTROL
+                        && key_event.code == KeyCode::Char('d')
+                    {
+                        self.mode = self.mode.next();
+                        if self.diff.is_some() {
+                            self.update_diff();
+                        }
+                        return;
+                    }
+                    if key_event.modifiers == KeyModifiers::CONTROL
+                        && key_event.code == KeyCode::Char('b')
+                    {
+                        self.backup_pos = 0;
+                        self.backups = match self.backups {
+                            Some(_) => None,
+                            None => Some(Backup::list(&Config::get_backup_dir())),
+                        };
+                        return;
+                    }
+                    if self.backups.is_some() {
+                        self.handle_backups_key(key_event.code);
+                        return;
+                    }
+                    if key_event.modifiers == KeyModifiers::CONTROL
+                        && key_event.code == KeyCode::Char('p')
+                    {
+                        if self.diff.is_some() {
+                            self.diff = None;
+                        } else {
+                            self.diff_scroll = 0;
+                            self.update_diff();
+                        }
+                        return;
+                    }
+                    if self.diff.is_some() {
+                        match key_event.code {
+                            KeyCode::PageDown => {
+                                self.diff_scroll = self.diff_scroll.saturating_add(10);
+                                return;
+                            }
+                            KeyCode::PageUp => {
+                                self.diff_scroll = self.diff_scroll.saturating_sub(10);
+                                return;
+                            }
+                            _ => {}
+                        }
+                    }
                     let change = self
                         .get_component(self.focused.current())
                         .handle_key_event_focused(&key_event);
@@ -136,16 +285,47 @@ impl ApplyPage {
                             self.get_component(res.new).set_focused(true);
                         }
                     }
+                    if self.diff.is_some() {
+                        self.update_diff();
+                    }
                 }
                 _ => {}
             },
             ApplyTui::ContinueButton() => {
-                self.cfg.process(&ReplaceColors {
-                    primary: self.selected_colors.primary.get_color(),
-                    secondary: self.selected_colors.secondary.get_color(),
-                    tertiary: self.selected_colors.tertiary.get_color(),
+                if self.applying {
+                    return;
+                }
+                self.applying = true;
+                self.continue_button.change_title("Applying...");
+                // hooks can take up to their timeout, the page keeps drawing meanwhile
+                let (cfg, palette) = (self.cfg.clone(), self.get_palette());
+                let tx = self.tx.clone();
+                tokio::task::spawn(async move {
+                    let report = tokio::task::spawn_blocking(move || cfg.process(&palette))
+                        .await
+                        .unwrap();
+                    tx.send(ApplyTui::Applied(report).into()).await.unwrap();
                 });
-                self.continue_button.change_title("Done!");
+            }
+            ApplyTui::Applied(report) => {
+                self.applying = false;
+                let failed = report.errors.len() + report.failed();
+                self.summary = report.summary();
+                self.errors = report.errors;
+                self.results = report.files;
+                self.hooks = report.hooks;
+                self.files = self.cfg.get_files();
+                if self.diff.is_some() {
+                    self.update_diff();
+                }
+                if report.aborted {
+                    self.continue_button.change_title("Aborted by hook");
+                } else if failed == 0 {
+                    self.continue_button.change_title("Done!");
+                } else {
+                    self.continue_button
+                        .change_title(format!("Failed: {failed}"));
+                }
                 let tx = self.tx.clone();
                 tokio::task::spawn(async move {
                     sleep(Duration::from_secs(2)).await;
@@ -153,6 +333,7 @@ impl ApplyPage {
                 });
                 self.completed = true;
             }
+            ApplyTui::Delayed if self.applying => {}
             ApplyTui::Delayed => {
                 self.continue_button.change_title("Replace");
             }
@@ -165,7 +346,8 @@ impl ApplyPage {
             .direction(Direction::Vertical)
             .constraints(vec![
                 Constraint::Max(3),
-                Constraint::Max(5),
+                Constraint::Max(self.selected_colors.roles.len() as u16 + 2),
+                Constraint::Max(3),
                 Constraint::Fill(1),
                 Constraint::Max(3),
                 Constraint::Max(3),
@@ -174,28 +356,90 @@ impl ApplyPage {
 
         let colors_bar = Layout::default()
             .direction(Direction::Vertical)
-            .constraints(vec![
-                Constraint::Max(1),
-                Constraint::Max(1),
-                Constraint::Max(1),
-            ])
+            .constraints(vec![Constraint::Max(1); self.selected_colors.roles.len()])
             .split(layout[1].inner(Margin::new(1, 1)));
         frame.render_widget(
             Block::bordered().border_type(BorderType::Rounded),
             layout[1],
         );
-        frame.render_widget(&self.selected_colors.primary, colors_bar[0]);
-        frame.render_widget(&self.selected_colors.secondary, colors_bar[1]);
-        frame.render_widget(&self.selected_colors.tertiary, colors_bar[2]);
+        let contrast = self.cfg.get_contrast();
+        for ((_, picker), area) in self.selected_colors.roles.iter().zip(colors_bar.iter()) {
+            let row = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(vec![Constraint::Fill(1), Constraint::Length(18)])
+                .split(*area);
+            frame.render_widget(picker, row[0]);
+
+            let color = RgbColor::from_str(&picker.get_color()).unwrap();
+            let on = color.readable();
+            let ratio = color.contrast(&on);
+            let contrast_line = Line::from(vec![
+                " Aa ".fg(Color::from(on)).bg(Color::from(color)),
+                format!(" {ratio:.2}:1 {contrast:?}").fg(if ratio >= contrast.ratio() {
+                    Color::Green
+                } else {
+                    Color::Red
+                }),
+            ]);
+            frame.render_widget(Paragraph::new(contrast_line), row[1]);
+        }
+
+        // harmonies of the focused role, or the first one when button is focused
+        let row = (self.focused.current().0 as usize).saturating_sub(1);
+        if let Some((name, picker)) = self.selected_colors.roles.get(row) {
+            let color = RgbColor::from_str(&picker.get_color()).unwrap();
+            let harmonies = Line::from(
+                Harmony::all()
+                    .iter()
+                    .flat_map(|harmony| {
+                        vec![
+                            symbols::block::FULL
+                                .repeat(3)
+                                .fg(Color::from(harmony.apply(&color))),
+                            format!(" {}  ", harmony.name()).into(),
+                        ]
+                    })
+                    .collect::<Vec<Span<'_>>>(),
+            );
+            frame.render_widget(
+                Paragraph::new(harmonies).centered().block(
+                    Block::bordered()
+                        .border_type(BorderType::Rounded)
+                        .title(format!("Derived from {name}:")),
+                ),
+                layout[2],
+            );
+        }
 
         let top_bar = Layout::default()
             .direction(Direction::Horizontal)
             .constraints(vec![Constraint::Fill(4), Constraint::Fill(1)])
             .split(layout[0]);
+        let problem_lines = self.errors.len() + self.problems.len();
+        let hook_lines = self
+            .hooks
+            .iter()
+            .flat_map(|x| x.lines())
+            .collect::<Vec<String>>();
+        let mut mid_constraints = vec![Constraint::Fill(1)];
+        if problem_lines > 0 {
+            mid_constraints.push(Constraint::Max(problem_lines as u16 + 2));
+        }
+        if !hook_lines.is_empty() {
+            mid_constraints.push(Constraint::Max(hook_lines.len() as u16 + 2));
+        }
+        let mid_bar = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(mid_constraints)
+            .split(layout[3]);
         let mid = Layout::default()
             .direction(Direction::Horizontal)
-            .constraints(vec![Constraint::Fill(1), Constraint::Fill(1)])
-            .split(layout[2]);
+            .constraints(if self.results.is_empty() {
+                vec![Constraint::Fill(1), Constraint::Fill(1)]
+            } else {
+                vec![Constraint::Fill(1), Constraint::Fill(1), Constraint::Fill(2)]
+            })
+            .split(mid_bar[0]);
 
         let instructions = Line::from(vec![
             "CTRL + Q".blue().reversed(), 
@@ -203,7 +447,18 @@ impl ApplyPage {
             "+(=)/-".blue().reversed(),
             ":Lighten/Darken color | ".into(),
             "R".blue().reversed(), 
-            ":Reset color".into(),
+            ":Reset color | ".into(),
+            "CTRL + P".blue().reversed(),
+            ":Preview changes | ".into(),
+            "CTRL + B".blue().reversed(),
+            ":Restore backup | ".into(),
+            "CTRL + D".blue().reversed(),
+            format!(
+                ":Mode {:?} ({})",
+                self.mode,
+                if self.get_palette().dark { "dark" } else { "light" }
+            )
+            .into(),
         ]);
         let ix = Paragraph::new(instructions)
             .block(Block::bordered().border_type(BorderType::Rounded))
@@ -219,11 +474,10 @@ impl ApplyPage {
             *top_bar.first().unwrap(),
         );
 
-        let files = self.cfg.get_files();
         let from_block = Paragraph::new(Text::from(
-            files
+            self.files
                 .iter()
-                .map(|x| x.from.to_str().to_owned().unwrap().into())
+                .map(|x| x.source().to_str().to_owned().unwrap().into())
                 .collect::<Vec<Line<'_>>>(),
         ))
         .block(
@@ -232,7 +486,7 @@ impl ApplyPage {
                 .title("Detected files:"),
         );
         let destination_block = Paragraph::new(Text::from(
-            files
+            self.files
                 .iter()
                 .map(|x| x.to.to_str().to_owned().unwrap().into())
                 .collect::<Vec<Line<'_>>>(),
@@ -242,15 +496,124 @@ impl ApplyPage {
                 .border_type(BorderType::Rounded)
                 .title("Destination:"),
         );
-        let current_colors = Paragraph::new(Text::from(vec!["A".into(), "B".into()])).block(
+        let results_block = Paragraph::new(Text::from(
+            self.results
+                .iter()
+                .map(|x| {
+                    let line = format!("{:<9} {}", x.status.name(), x.to.display());
+                    let line = match x.status.reason() {
+                        Some(reason) => format!("{line}: {reason}"),
+                        None => line,
+                    };
+                    match x.status {
+                        FileStatus::Written => line.green().into(),
+                        FileStatus::Unchanged => line.into(),
+                        FileStatus::Skipped(_) => line.yellow().into(),
+                        FileStatus::Failed(_) => line.red().into(),
+                    }
+                })
+                .collect::<Vec<Line<'_>>>(),
+        ))
+        .block(
             Block::bordered()
                 .border_type(BorderType::Rounded)
-                .title("Chosen colors"),
+                .title(format!("Last apply: {}", self.summary)),
         );
         frame.render_widget(&self.continue_button, *top_bar.last().unwrap());
-        frame.render_widget(&self.color_component, layout[3]);
-        frame.render_widget(from_block, mid[0]);
-        frame.render_widget(destination_block, mid[1]);
+        frame.render_widget(&self.color_component, layout[4]);
+        if let Some(backups) = &self.backups {
+            let lines = if backups.is_empty() {
+                vec!["No backups yet".into()]
+            } else {
+                backups
+                    .iter()
+                    .enumerate()
+                    .map(|(pos, backup)| {
+                        let line = format!("{}. {}", pos + 1, backup.summary());
+                        if pos == self.backup_pos {
+                            line.reversed().into()
+                        } else {
+                            line.into()
+                        }
+                    })
+                    .collect::<Vec<Line<'_>>>()
+            };
+            let backups_block = Paragraph::new(Text::from(lines))
+                .scroll(((self.backup_pos as u16).saturating_sub(mid_bar[0].height / 2), 0))
+                .block(
+                    Block::bordered()
+                        .border_type(BorderType::Rounded)
+                        .title("Restore files as they were before (Enter to restore, Esc to close):"),
+                );
+            frame.render_widget(backups_block, mid_bar[0]);
+        } else if let Some(diff) = &self.diff {
+            let diff_block = Paragraph::new(Text::from(
+                diff.iter()
+                    .map(|x| {
+                        if x.starts_with("+++") || x.starts_with("---") {
+                            x.clone().bold().into()
+                        } else if x.starts_with('+') {
+                            x.clone().green().into()
+                        } else if x.starts_with('-') {
+                            x.clone().red().into()
+                        } else if x.starts_with("@@") {
+                            x.clone().cyan().into()
+                        } else if x.starts_with('!') {
+                            x.clone().yellow().into()
+                        } else {
+                            x.clone().into()
+                        }
+                    })
+                    .collect::<Vec<Line<'_>>>(),
+            ))
+            .scroll((self.diff_scroll, 0))
+            .block(
+                Block::bordered()
+                    .border_type(BorderType::Rounded)
+                    .title("Changes (PageUp/PageDown to scroll):"),
+            );
+            frame.render_widget(diff_block, mid_bar[0]);
+        } else {
+            frame.render_widget(from_block, mid[0]);
+            frame.render_widget(destination_block, mid[1]);
+            if !self.results.is_empty() {
+                frame.render_widget(results_block, mid[2]);
+            }
+        }
+        if problem_lines > 0 {
+            let errors_block = Paragraph::new(Text::from(
+                self.problems
+                    .iter()
+                    .map(|x| x.clone().yellow().into())
+                    .chain(self.errors.iter().map(|x| x.clone().red().into()))
+                    .collect::<Vec<Line<'_>>>(),
+            ))
+            .block(
+                Block::bordered()
+                    .border_type(BorderType::Rounded)
+                    .title("Problems:"),
+            );
+            frame.render_widget(errors_block, mid_bar[1]);
+        }
+        if !hook_lines.is_empty() {
+            let lines = self
+                .hooks
+                .iter()
+                .flat_map(|hook| {
+                    hook.lines().into_iter().enumerate().map(|(pos, line)| match pos {
+                        0 if hook.success() => line.green().into(),
+                        0 => line.red().into(),
+                        _ => line.into(),
+                    })
+                })
+                .collect::<Vec<Line<'_>>>();
+            let hooks_block = Paragraph::new(Text::from(lines)).block(
+                Block::bordered()
+                    .border_type(BorderType::Rounded)
+                    .title("Hooks:"),
+            );
+            frame.render_widget(hooks_block, *mid_bar.last().unwrap());
+        }
         // self.image_ui.render_image(frame, layout[1]);
         // frame.render_widget(&self.colors, layout[2]);
     }
@@ -136,6 +136,7 @@ impl ImageInputPage {
                         ImageData {
                             image_path: self.image_path.get_value(),
                             colors: x,
+                            shares: self.colors.get_shares(),
                         }
                     ))).unwrap();
                 } else {
@@ -1,3 +1,4 @@
 pub mod image_input;
 pub mod apply_page;
+pub mod validation_page;
 pub mod warning_page;
\ No newline at end of file
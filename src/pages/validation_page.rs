@@ -0,0 +1,85 @@
+use crate::{
+    app::{Pages, Tui},
+    helpers::{config::Config, validation::ValidationError},
+};
+use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::{
+    layout::{Alignment, Constraint, Direction, Layout},
+    style::{Color, Stylize},
+    text::{Line, Text},
+    widgets::{Block, BorderType, Paragraph, Wrap},
+    Frame,
+};
+use tokio::sync::mpsc::Sender;
+
+/// Shown before the image page when config has problems
+pub struct ValidationPage {
+    tx: Sender<Tui>,
+    cfg: Config,
+    pub problems: Vec<ValidationError>,
+}
+
+impl ValidationPage {
+    pub fn new(tx: Sender<Tui>, cfg: Config) -> Self {
+        Self {
+            problems: cfg.validate(),
+            tx,
+            cfg,
+        }
+    }
+
+    pub fn handle_event(&mut self, event: Event) {
+        let Event::Key(key_event) = event else {
+            return;
+        };
+        if key_event.kind != KeyEventKind::Press {
+            return;
+        }
+        match key_event.code {
+            KeyCode::Char('r') if key_event.modifiers == KeyModifiers::CONTROL => {
+                self.problems = self.cfg.validate();
+            }
+            KeyCode::Enter => {
+                self.tx.try_send(Tui::ChangePage(Pages::Image)).unwrap();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame) {
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Fill(1), Constraint::Max(3)])
+            .split(frame.area());
+        let lines = if self.problems.is_empty() {
+            vec![Line::from("No problems found").fg(Color::Green)]
+        } else {
+            self.problems
+                .iter()
+                .map(|x| Line::from(x.to_string()).fg(Color::Red))
+                .collect::<Vec<Line>>()
+        };
+        let problems = Paragraph::new(Text::from(lines))
+            .wrap(Wrap { trim: false })
+            .block(
+                Block::bordered()
+                    .border_type(BorderType::Rounded)
+                    .title(format!("Config problems: {}", self.problems.len())),
+            );
+        let instructions = Line::from(vec![
+            "CTRL + Q".blue().reversed(),
+            ":Quit".into(),
+            " | ".into(),
+            "Enter".blue().reversed(),
+            ":Continue".into(),
+            " | ".into(),
+            "CTRL + R".blue().reversed(),
+            ":Check again".into(),
+        ]);
+        let ix = Paragraph::new(instructions)
+            .block(Block::bordered().border_type(BorderType::Rounded))
+            .alignment(Alignment::Center);
+        frame.render_widget(problems, layout[0]);
+        frame.render_widget(ix, layout[1]);
+    }
+}
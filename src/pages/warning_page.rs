@@ -6,7 +6,7 @@ use crate::{
         image::{CustomImage, ImageState, ImageStruct},
         input_bar::Input,
     },
-    helpers::config::{Config, ReplaceColors},
+    helpers::config::{Config, Role},
     traits::{
         focus_tracker::FocusTracker,
         get_input::{get_axis, DefaultInputComponent, InputComponent},
@@ -23,11 +23,13 @@ use ratatui::{
 };
 use tokio::sync::mpsc::{self, Receiver, Sender};
 
-pub struct WarningPage {}
+pub struct WarningPage {
+    roles: Vec<Role>,
+}
 
 impl WarningPage {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(roles: Vec<Role>) -> Self {
+        Self { roles }
     }
 
     pub fn draw(&mut self, frame: &mut Frame) {
@@ -54,9 +56,9 @@ impl WarningPage {
         .alignment(Alignment::Center);
 
         let example_in: Paragraph<'_> = Paragraph::new(Text::from(
-            ReplaceColors::<String>::get_params()
+            self.roles
                 .iter()
-                .map(|x| format!("param  = {}", Config::replace_key(x.clone())).into())
+                .map(|x| format!("param  = {}", Config::replace_key(x.name.clone())).into())
                 .collect::<Vec<Line>>(),
         ))
         .block(
@@ -65,7 +67,7 @@ impl WarningPage {
                 .title("Example of 'from' file"),
         );
         let example_out: Paragraph<'_> =Paragraph::new(Text::from(
-            ReplaceColors::<String>::get_params()
+            self.roles
                 .iter()
                 .map(|x| "param = #ffffff".into())
                 .collect::<Vec<Line>>(),